@@ -9,6 +9,7 @@ use parameters::SamplerParams;
 // use crate::parameter::*;
 mod editor;
 mod resources;
+mod sf2;
 pub mod utils;
 
 mod ui;
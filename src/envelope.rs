@@ -0,0 +1,79 @@
+use crate::parameters::SamplerParams;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EnvStage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off,
+}
+
+/// Linear ADSR amplitude envelope. The times are read from the params every sample, so automation takes effect right away
+#[derive(Clone)]
+pub struct Envelope {
+    pub stage: EnvStage,
+    pub output: f32,
+    // the level the release stage started from, so the release time doesn't depend on the sustain level
+    release_level: f32,
+}
+
+impl Envelope {
+    pub fn new() -> Envelope {
+        Envelope {
+            stage: EnvStage::Off,
+            output: 0.,
+            release_level: 0.,
+        }
+    }
+    /// Starts the attack from the current output, so retriggering a sounding voice doesn't click
+    pub fn trigger(&mut self) {
+        self.stage = EnvStage::Attack;
+    }
+    pub fn release(&mut self) {
+        if self.stage != EnvStage::Off {
+            self.stage = EnvStage::Release;
+            self.release_level = self.output;
+        }
+    }
+    /// Silences the envelope immediately
+    pub fn reset(&mut self) {
+        self.stage = EnvStage::Off;
+        self.output = 0.;
+    }
+    pub fn is_off(&self) -> bool {
+        self.stage == EnvStage::Off
+    }
+    pub fn next(&mut self, params: &SamplerParams, sample_rate: f32) -> f32 {
+        // times 2 because of internal oversampling. Times are in ms
+        let samples_per_ms = sample_rate * 2. / 1000.;
+        let sustain = params.sustain.value;
+        match self.stage {
+            EnvStage::Attack => {
+                self.output += 1. / (params.attack.value * samples_per_ms).max(1.);
+                if self.output >= 1. {
+                    self.output = 1.;
+                    self.stage = EnvStage::Decay;
+                }
+            }
+            EnvStage::Decay => {
+                self.output -= (1. - sustain) / (params.decay.value * samples_per_ms).max(1.);
+                if self.output <= sustain {
+                    self.output = sustain;
+                    self.stage = EnvStage::Sustain;
+                }
+            }
+            EnvStage::Sustain => {
+                self.output = sustain;
+            }
+            EnvStage::Release => {
+                self.output -= self.release_level / (params.release.value * samples_per_ms).max(1.);
+                if self.output <= 0. {
+                    self.reset();
+                }
+            }
+            EnvStage::Off => {}
+        }
+        self.output
+    }
+}
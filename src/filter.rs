@@ -0,0 +1,111 @@
+// Per-voice multimode filter built from trapezoidal state variable filters
+// based on Andrew Simper's paper https://cytomic.com/files/dsp/SvfLinearTrapOptimised2.pdf
+use std::f32::consts::PI;
+
+use crate::parameters::{FilterSlope, FilterType, SamplerParams};
+use crate::sampler::SmoothedValues;
+
+// damping of the second stage of the 24 dB filter. Butterworth, so all the resonance comes from the first stage
+const BUTTERWORTH_K: f32 = std::f32::consts::SQRT_2;
+
+#[derive(Clone, Copy, Default)]
+struct Coefs {
+    k: f32,
+    a1: f32,
+    a2: f32,
+    a3: f32,
+}
+impl Coefs {
+    fn new(cutoff: f32, k: f32, sample_rate: f32) -> Coefs {
+        let g = (PI * cutoff / sample_rate).tan();
+        let a1 = 1. / (1. + g * (g + k));
+        let a2 = g * a1;
+        let a3 = g * a2;
+        Coefs { k, a1, a2, a3 }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct SvfState {
+    ic1eq: f32,
+    ic2eq: f32,
+}
+impl SvfState {
+    fn process(&mut self, input: f32, coefs: &Coefs, filter_type: FilterType) -> f32 {
+        let v3 = input - self.ic2eq;
+        let v1 = coefs.a1 * self.ic1eq + coefs.a2 * v3;
+        let v2 = self.ic2eq + coefs.a2 * self.ic1eq + coefs.a3 * v3;
+        self.ic1eq = 2. * v1 - self.ic1eq;
+        self.ic2eq = 2. * v2 - self.ic2eq;
+        match filter_type {
+            FilterType::Off => input,
+            FilterType::LowPass => v2,
+            FilterType::HighPass => input - coefs.k * v1 - v2,
+            FilterType::BandPass => v1,
+            FilterType::Notch => input - coefs.k * v1,
+        }
+    }
+}
+
+/// Stereo filter with 12 or 24 dB slopes
+#[derive(Clone)]
+pub struct VoiceFilter {
+    // indexed by [stage][channel]. The second stage is only used for 24 dB slopes
+    states: [[SvfState; 2]; 2],
+    first_stage: Coefs,
+    second_stage: Coefs,
+    // the coefficients are only recalculated when these change: cutoff, resonance, note and keytrack amount
+    inputs: [f32; 4],
+}
+
+impl VoiceFilter {
+    pub fn new() -> VoiceFilter {
+        VoiceFilter {
+            states: [[SvfState::default(); 2]; 2],
+            first_stage: Coefs::default(),
+            second_stage: Coefs::default(),
+            inputs: [-1.; 4],
+        }
+    }
+    pub fn reset(&mut self) {
+        self.states = [[SvfState::default(); 2]; 2];
+    }
+    /// note is the voice's current pitch in midi notes, pitch bend included, used for keyboard tracking
+    pub fn process(
+        &mut self,
+        input: [f32; 2],
+        params: &SamplerParams,
+        values: &SmoothedValues,
+        note: f32,
+        sample_rate: f32,
+    ) -> [f32; 2] {
+        let filter_type = params.filter_type.value();
+        if filter_type == FilterType::Off {
+            return input;
+        }
+        let keytrack = params.filter_keytrack.value;
+        let inputs = [values.cutoff, values.resonance, note, keytrack];
+        if inputs != self.inputs {
+            self.inputs = inputs;
+            // the filter runs at the internal, oversampled rate
+            let rate = sample_rate * 2.;
+            // the cutoff is tracked relative to middle C
+            let cutoff =
+                (values.cutoff * 2f32.powf((note - 60.) / 12. * keytrack)).clamp(20., rate * 0.49);
+            // k is the damping, the filter gets close to self oscillation at max resonance
+            let k = 2. - 1.95 * values.resonance;
+            self.first_stage = Coefs::new(cutoff, k, rate);
+            self.second_stage = Coefs::new(cutoff, BUTTERWORTH_K, rate);
+        }
+        let mut output = [0.; 2];
+        for (channel, out) in output.iter_mut().enumerate() {
+            *out = self.states[0][channel].process(input[channel], &self.first_stage, filter_type);
+        }
+        if params.filter_slope.value() == FilterSlope::Db24 {
+            for (channel, out) in output.iter_mut().enumerate() {
+                *out = self.states[1][channel].process(*out, &self.second_stage, filter_type);
+            }
+        }
+        output
+    }
+}
@@ -1,27 +1,57 @@
 #![feature(portable_simd)]
 use halfband::HalfbandFilter;
+use loader::{LoadRequest, SampleLoader};
+use nih_plug::param::smoothing::Smoother;
 use nih_plug::prelude::*;
+use sampler::{to_midi_velocity, Keymap, RoundRobinState, SmoothedValues};
+use std::cmp::Ordering;
 use std::sync::Arc;
 use voice::Voice;
 
 mod editor;
+mod envelope;
+mod filter;
 mod parameters;
 mod sampler;
 pub mod utils;
-use parameters::{OnOff, SamplerParams};
+use parameters::{
+    GlideMode, NotePriority, OnOff, SamplerParams, StealMode, TriggerMode, MAX_VOICES,
+};
 mod ui;
 
 mod halfband;
+mod loader;
 mod resources;
+mod sf2;
+mod sfz;
 mod voice;
 
+const SUSTAIN_CC: u8 = 64;
+const SOSTENUTO_CC: u8 = 66;
+// in ms
+const PITCHBEND_SMOOTHING_TIME: f32 = 10.;
+
 pub struct Plug<'a> {
     params: Arc<SamplerParams>,
     pub sampler: sampler::Sampler<'a>,
+    loader: SampleLoader,
+    // a sample change that came in while the loader thread was stopped
+    load_pending: bool,
 
     sample_rate: f32,
     pressed_notes: Vec<u8>,
     voices: Vec<Voice>,
+    // counts triggered notes, so voices know how old they are
+    note_count: u64,
+    // counts key presses, so the layers of a press can be told apart from the ones of other presses of the key
+    press_count: u64,
+    sustain_down: bool,
+    sostenuto_down: bool,
+    round_robin: RoundRobinState,
+    // the velocity each key was last pressed with, for the zones that play when it's let go
+    note_velocities: [f32; 128],
+    // smooths out the steps between pitch bend messages
+    pitchbend: Smoother<f32>,
     halfband: [HalfbandFilter; 2],
 }
 
@@ -29,108 +59,386 @@ impl<'a> Plug<'a> {
     pub fn note_off(&mut self, note: u8) {
         // remove all copies of the note from pressed_notes
         self.pressed_notes.retain(|x| x != &note);
-        for i in 0..self.voices.len() {
-            if self.voices[i].current_note == note && self.voices[i].is_on {
-                // if there are more notes left, toss one of them into the voice
-                // TODO: If we switch to last_note dropping, we could probably extend this to working in poly mode
-                if self.params.mono.value() == OnOff::On && !self.pressed_notes.is_empty() {
+        if self.params.mono.value() == OnOff::Off {
+            self.trigger_release_zones(note);
+        }
+        // one-shot voices play until they run out of sample, the key being let go doesn't matter
+        if self.params.trigger_mode.value() == TriggerMode::OneShot {
+            return;
+        }
+        if self.params.mono.value() == OnOff::On {
+            // releasing a note that isn't playing doesn't change anything
+            if self.voices[0].current_note == note && self.voices[0].is_on {
+                // if there are more notes left, toss the one with the highest priority into the voice
+                // the held note's own velocity isn't known, so keep the voice's
+                let velocity = self.voices[0].velocity;
+                let next_zone = self
+                    .priority_note()
+                    .and_then(|next_note| Some((next_note, self.first_zone(next_note, velocity)?)));
+                if let Some((next_note, zone)) = next_zone {
                     self.trigger_voice(
-                        self.pressed_notes[0],
-                        // self.params.legato.value() == OnOff::On,
-                        false,
+                        next_note,
+                        velocity,
+                        self.params.legato.value() == OnOff::On,
                         0,
+                        zone,
                     )
                 }
                 // if not, just release it
                 else {
-                    self.voices[i].release();
+                    self.release_voice(0);
+                }
+            }
+            return;
+        }
+        // every zone the press started has its own voice, and they're all let go together
+        if let Some(press) = held_press(&self.voices, note) {
+            for i in 0..self.voices.len() {
+                if self.voices[i].is_held_by(note, press) {
+                    self.release_voice(i);
                 }
-                break;
             }
         }
     }
-    pub fn note_on(&mut self, note: u8) {
-        // for safety, remove note from pressed_notes if note is already there
+    // releases the voice, unless one of the pedals is holding it or it's playing a one-shot zone
+    fn release_voice(&mut self, voice_n: usize) {
+        if self.sampler.is_one_shot(&self.voices[voice_n]) {
+            return;
+        }
+        let voice = &mut self.voices[voice_n];
+        if self.sustain_down || (self.sostenuto_down && voice.sostenuto) {
+            voice.sustained = true;
+        } else {
+            voice.release();
+        }
+    }
+    pub fn set_sustain(&mut self, down: bool) {
+        self.sustain_down = down;
+        if !down {
+            self.release_sustained_voices();
+        }
+    }
+    pub fn set_sostenuto(&mut self, down: bool) {
+        if down && !self.sostenuto_down {
+            // sostenuto only holds on to the notes that are held when the pedal goes down
+            for voice in self.voices.iter_mut() {
+                voice.sostenuto = voice.is_on && !voice.sustained;
+            }
+        }
+        self.sostenuto_down = down;
+        if !down {
+            for voice in self.voices.iter_mut() {
+                voice.sostenuto = false;
+            }
+            self.release_sustained_voices();
+        }
+    }
+    fn release_sustained_voices(&mut self) {
+        for i in 0..self.voices.len() {
+            // goes through release_voice again, since the other pedal might still be holding the voice
+            if self.voices[i].sustained {
+                self.release_voice(i);
+            }
+        }
+    }
+    // the held note that should be playing in mono mode
+    fn priority_note(&self) -> Option<u8> {
+        match self.params.note_priority.value() {
+            NotePriority::Last => self.pressed_notes.last().copied(),
+            NotePriority::First => self.pressed_notes.first().copied(),
+            NotePriority::Lowest => self.pressed_notes.iter().min().copied(),
+            NotePriority::Highest => self.pressed_notes.iter().max().copied(),
+        }
+    }
+    pub fn note_on(&mut self, note: u8, velocity: f32) {
+        // for safety, remove note from pressed_notes if note is already there.
+        // The order is kept, since it decides the note priority
         if let Some(pos) = self.pressed_notes.iter().position(|x| x == &note) {
-            self.pressed_notes.swap_remove(pos);
+            self.pressed_notes.remove(pos);
         }
         self.pressed_notes.push(note);
-        // let legato = self.params.legato.value() == OnOff::On;
-        let legato = false;
-        // if the synth is in mono mode, just trigger the first voice
+        self.press_count += 1;
+        self.note_velocities[note as usize % 128] = velocity;
+        let midi_velocity = to_midi_velocity(velocity);
+        // the zones taking turns on the key play the current step, and the press moves the key on to the next one
+        let step = self.round_robin.step(note);
+        // legato only makes sense when there's a single voice to glide
+        let legato =
+            self.params.mono.value() == OnOff::On && self.params.legato.value() == OnOff::On;
+        // if the synth is in mono mode, just trigger the first voice, if the new note has priority
         if self.params.mono.value() == OnOff::On {
-            self.trigger_voice(note, legato, 0);
+            if self.priority_note() == Some(note) {
+                if let Some(zone) = self.first_zone(note, velocity) {
+                    self.trigger_voice(note, velocity, legato, 0, zone);
+                }
+            }
         } else {
-            for i in 0..self.voices.len() {
-                // if self.voices[i].vol_env.output.is_none() {
-                if !self.voices[i].is_on {
-                    self.trigger_voice(note, legato, i);
-
-                    // if this happens, we've found a free voice and can safely return
-                    return;
+            if self.params.retrigger.value() == OnOff::On {
+                for voice in self.voices.iter_mut() {
+                    if voice.is_active() && voice.current_note == note {
+                        voice.declick(self.sample_rate);
+                    }
                 }
             }
-            // Finding voice with the lowest current_note
-            // self.voices.iter().enumerate().map(|x| x_current_note).min_by(|x, y| x.cmp(y)).unwrap()
-            let mut lowest_note = 128;
-            let mut voice_number = 50;
-
-            for i in 0..self.voices.len() {
-                if self.voices[i].current_note < lowest_note {
-                    lowest_note = self.voices[i].current_note;
-                    voice_number = i;
+            // all the choking is done before any zone is triggered, so layers of the same note don't cut each other off
+            let mut zone = self
+                .sampler
+                .active_keymap()
+                .next_zone(0, note, midi_velocity, step);
+            while let Some(z) = zone {
+                let (choke_group, _) = self.choke_group(z);
+                for voice in self.voices.iter_mut() {
+                    if choke_group != 0 && voice.is_active() && voice.off_by == choke_group {
+                        voice.declick(self.sample_rate);
+                    }
                 }
+                zone = self
+                    .sampler
+                    .active_keymap()
+                    .next_zone(z + 1, note, midi_velocity, step);
+            }
+            // every zone the note and velocity fall into gets its own voice
+            let mut zone = self
+                .sampler
+                .active_keymap()
+                .next_zone(0, note, midi_velocity, step);
+            while let Some(z) = zone {
+                self.allocate_voice(note, velocity, z);
+                zone = self
+                    .sampler
+                    .active_keymap()
+                    .next_zone(z + 1, note, midi_velocity, step);
             }
-            self.trigger_voice(note, legato, voice_number);
         }
+        let round_robin_len = self
+            .sampler
+            .active_keymap()
+            .round_robin_len(note, midi_velocity);
+        self.round_robin
+            .advance(note, round_robin_len, self.params.round_robin.value());
     }
-    fn trigger_voice(&mut self, note: u8, legato: bool, voice_n: usize) {
+    // plays the zones that are triggered by letting go of the key, with the velocity the key was pressed with
+    fn trigger_release_zones(&mut self, note: u8) {
+        let velocity = self.note_velocities[note as usize % 128];
+        let midi_velocity = to_midi_velocity(velocity);
+        let mut zone = self
+            .sampler
+            .active_keymap()
+            .next_release_zone(0, note, midi_velocity);
+        while let Some(z) = zone {
+            let voice_n = self.allocate_voice(note, velocity, z);
+            // no key is holding the voice, so it plays until the end of its sample
+            self.voices[voice_n].is_on = false;
+            zone = self
+                .sampler
+                .active_keymap()
+                .next_release_zone(z + 1, note, midi_velocity);
+        }
+    }
+    // finds a voice for the zone in poly mode, stealing one if the polyphony is used up. Returns the voice's index
+    fn allocate_voice(&mut self, note: u8, velocity: f32, zone: usize) -> usize {
+        // voices that are fading out don't count towards the polyphony
+        let n_voices = self.params.voices.value as usize;
+        let voices_in_use = self
+            .voices
+            .iter()
+            .filter(|voice| voice.is_active() && !voice.is_declicking())
+            .count();
+        let free_voice = self.voices.iter().position(|voice| !voice.is_active());
+        if let Some(i) = free_voice {
+            if voices_in_use < n_voices {
+                self.trigger_voice(note, velocity, false, i, zone);
+                return i;
+            }
+        }
+        let stolen_voice = self.voice_to_steal();
+        match free_voice {
+            // the stolen voice fades out while the new note starts in a free voice
+            Some(i) => {
+                self.voices[stolen_voice].declick(self.sample_rate);
+                self.trigger_voice(note, velocity, false, i, zone);
+                i
+            }
+            // no room left for the fade, so the new note takes over the voice directly
+            None => {
+                self.trigger_voice(note, velocity, false, stolen_voice, zone);
+                stolen_voice
+            }
+        }
+    }
+    // fades out the oldest voices when the polyphony has been lowered below the number of voices playing
+    fn limit_voices(&mut self) {
+        let n_voices = self.params.voices.value as usize;
+        let is_playing = |voice: &Voice| voice.is_active() && !voice.is_declicking();
+        let mut voices_in_use = self.voices.iter().filter(|voice| is_playing(voice)).count();
+        while voices_in_use > n_voices {
+            let oldest = self
+                .voices
+                .iter()
+                .enumerate()
+                .filter(|(_, voice)| is_playing(voice))
+                .min_by_key(|(_, voice)| voice.started);
+            match oldest {
+                Some((i, _)) => self.voices[i].declick(self.sample_rate),
+                None => break,
+            }
+            voices_in_use -= 1;
+        }
+    }
+    // the first zone of the active keymap that plays on the note and velocity
+    fn first_zone(&self, note: u8, velocity: f32) -> Option<usize> {
+        self.sampler.active_keymap().next_zone(
+            0,
+            note,
+            to_midi_velocity(velocity),
+            self.round_robin.step(note),
+        )
+    }
+    // the group a voice playing the zone belongs to, and the group that cuts it off.
+    // Choke groups come from the keymap: sfz's group and off_by, and sf2's exclusive class
+    fn choke_group(&self, zone: usize) -> (u32, u32) {
+        match self.sampler.active_keymap().zones.get(zone) {
+            Some(zone) => (zone.group, zone.off_by),
+            None => (0, 0),
+        }
+    }
+    fn voice_to_steal(&self) -> usize {
+        let candidates = self
+            .voices
+            .iter()
+            .enumerate()
+            .filter(|(_, voice)| voice.is_active() && !voice.is_declicking());
+        let quieter = |a: &(usize, &Voice), b: &(usize, &Voice)| {
+            a.1.loudness()
+                .partial_cmp(&b.1.loudness())
+                .unwrap_or(Ordering::Equal)
+        };
+        let (stolen_voice, _) = match self.params.steal_mode.value() {
+            StealMode::Oldest => candidates.min_by_key(|(_, voice)| voice.started),
+            StealMode::Quietest => candidates.min_by(quieter),
+            StealMode::Lowest => candidates.min_by_key(|(_, voice)| voice.current_note),
+            StealMode::Highest => candidates.max_by_key(|(_, voice)| voice.current_note),
+            StealMode::PreferReleased => candidates
+                .clone()
+                .filter(|(_, voice)| !voice.is_on)
+                .min_by(quieter)
+                .or_else(|| candidates.min_by_key(|(_, voice)| voice.started)),
+        }
+        // only happens if every voice is fading out already
+        .unwrap_or((0, &self.voices[0]));
+        stolen_voice
+    }
+    fn trigger_voice(
+        &mut self,
+        note: u8,
+        velocity: f32,
+        legato: bool,
+        voice_n: usize,
+        zone: usize,
+    ) {
+        self.note_count += 1;
+        let velocity_gain = sampler::calc_velocity_gain(
+            velocity,
+            self.params.vel_amount.value,
+            self.params.vel_curve.value(),
+        );
+        let (_, off_by) = self.choke_group(zone);
         let voice = &mut self.voices[voice_n];
-        if voice.is_on {
-            // if voice is already playing and legato is on, don't restart envs
+        // the voice belongs to a held key now
+        voice.sustained = false;
+        voice.sostenuto = false;
+        // a voice that is being faded out can't be glided, so it gets restarted instead
+        if voice.is_on && !voice.is_declicking() {
+            // if voice is already playing and legato is on, don't restart envs or the sample
             if !legato {
-                // voice.vol_env.trigger_env();
+                voice.started = self.note_count;
+                voice.velocity = velocity;
+                voice.velocity_gain = velocity_gain;
+                voice.vol_env.trigger();
                 // voice.mod_matrix.trigger(velocity);
+                voice.slot = self.sampler.active_slot();
+                voice.zone = zone;
+                voice.off_by = off_by;
+                self.sampler.reset_phase(&self.params, voice);
             }
             // if voice is already playing, it should glide
             // since glide_time is gotten here instead of per sample,
             // changes only take effect to notes happeining after any change to glide_time
             voice.current_note = note;
             voice.target_notepitch = note as f32;
-            // let glide_time = self.params.glide_time.value;
-            let glide_time = 0.;
-            // this formula finds an increment value fitting the glide time
-            voice.increment = (voice.target_notepitch - voice.current_notepitch)
-                / (glide_time * self.sample_rate * 2.) as f32;
+            let glide_time = self.params.glide_time.value / 1000.;
+            if glide_time <= 0. {
+                voice.current_notepitch = voice.target_notepitch;
+                voice.increment = 0.;
+            } else {
+                let distance = voice.target_notepitch - voice.current_notepitch;
+                // this formula finds an increment value fitting the glide time. Times 2 because of internal oversampling
+                voice.increment = match self.params.glide_mode.value() {
+                    GlideMode::ConstantTime => distance / (glide_time * self.sample_rate * 2.),
+                    GlideMode::ConstantRate => {
+                        12. * distance.signum() / (glide_time * self.sample_rate * 2.)
+                    }
+                };
+            }
         } else {
             // if voice was not on, just set pitch, trigger envelopes and reset phase
             voice.is_on = true;
+            voice.reset_declick();
+            voice.slot = self.sampler.active_slot();
+            voice.zone = zone;
+            voice.off_by = off_by;
             voice.current_note = note;
             voice.current_notepitch = note as f32;
             voice.target_notepitch = note as f32;
             voice.increment = 0.;
-            // voice.vol_env.trigger_env();
+            voice.started = self.note_count;
+            voice.velocity = velocity;
+            voice.velocity_gain = velocity_gain;
+            voice.vol_env.trigger();
+            voice.filter.reset();
             // voice.mod_matrix.trigger(velocity);
-            self.voices[voice_n].sampler_phase = self.params.pos.value;
+            self.sampler.reset_phase(&self.params, voice);
         }
-        self.sampler
-            .voice_pitch_changed(&self.params, &self.voices, voice_n);
-        self.sampler.is_done[voice_n] = false;
+        let voice = &mut self.voices[voice_n];
+        voice.press = self.press_count;
+        voice.is_done = false;
+        voice.out_of_range = false;
+        self.sampler.voice_pitch_changed(&self.params, voice);
     }
 }
 
-impl<'a> Default for Plug<'a> {
+// the oldest press of the note that is still holding voices. When a key is pressed again before it's let go,
+// the first note off belongs to the first press
+fn held_press(voices: &[Voice], note: u8) -> Option<u64> {
+    voices
+        .iter()
+        .filter(|voice| voice.current_note == note && voice.is_on && !voice.sustained)
+        .map(|voice| voice.press)
+        .min()
+}
+
+impl Default for Plug<'static> {
     fn default() -> Self {
         let params = Arc::new(SamplerParams::default());
-        let mut sampler = sampler::Sampler::new();
-
-        sampler.source_changed(&params);
+        // the first sample is loaded right away, every sample after that goes through the loader thread
+        let sampler = sampler::Sampler::new(Box::new(Keymap::from_params(&params)));
         Self {
             sampler,
+            loader: SampleLoader::new(params.clone()),
+            load_pending: false,
             params,
             sample_rate: 48000.,
-            voices: vec![Voice::new(); 8],
-            pressed_notes: vec![],
+            // twice the max polyphony, so stolen voices have room to fade out
+            voices: vec![Voice::new(); MAX_VOICES * 2],
+            note_count: 0,
+            press_count: 0,
+            sustain_down: false,
+            sostenuto_down: false,
+            round_robin: RoundRobinState::new(),
+            note_velocities: [1.; 128],
+            pitchbend: Smoother::new(SmoothingStyle::Linear(PITCHBEND_SMOOTHING_TIME)),
+            // room for every midi note, so pushing never allocates
+            pressed_notes: Vec::with_capacity(128),
             halfband: [HalfbandFilter::new(12, true), HalfbandFilter::new(12, true)],
         }
     }
@@ -147,7 +455,7 @@ impl Plugin for Plug<'static> {
     const DEFAULT_NUM_INPUTS: u32 = 0;
     const DEFAULT_NUM_OUTPUTS: u32 = 2;
 
-    const MIDI_INPUT: MidiConfig = MidiConfig::Basic;
+    const MIDI_INPUT: MidiConfig = MidiConfig::MidiCCs;
     // const ACCEPTS_MIDI: bool = true;
 
     fn params(&self) -> Arc<dyn Params> {
@@ -171,6 +479,11 @@ impl Plugin for Plug<'static> {
         let rate = _buffer_config.sample_rate;
         self.sampler.set_standard_pitch(rate);
         self.sample_rate = rate;
+        // a loader thread that stopped is brought back here, where allocating is fine
+        if self.loader.restart_if_stopped() && self.load_pending {
+            self.load_pending = false;
+            self.params.source_changed.set_release(true);
+        }
         true
     }
 
@@ -178,27 +491,54 @@ impl Plugin for Plug<'static> {
         // use the param updates that has happened since last buffer
         if self.params.pitch_changed.check_reset() {
             self.sampler
-                .pitch_params_changed(&self.params, &self.voices)
+                .pitch_params_changed(&self.params, &mut self.voices)
         }
+        self.limit_voices();
         if self.params.source_changed.check_reset() {
-            // load the data
-            self.params.load_sample();
-            // perform pre-processing
-            self.sampler.source_changed(&self.params);
+            // loading and pre-processing happens on the loader thread
+            match self.loader.request_load() {
+                LoadRequest::Queued => (),
+                // try again next block
+                LoadRequest::Full => self.params.source_changed.set_release(true),
+                // the loader can't be restarted from the audio thread, so the load waits for the next initialize
+                LoadRequest::Stopped => self.load_pending = true,
+            }
+        }
+        if let Some(data) = self.loader.try_receive() {
+            if let Some(unused_data) = self.sampler.set_data(data) {
+                self.loader.dispose(unused_data);
+            }
+        }
+        // the new sample is swapped in once no voice is playing from the slot it goes into
+        if let Some(old_data) = self.sampler.swap_data(&mut self.voices) {
+            self.loader.dispose(old_data);
         }
 
         let mut next_event = context.next_event();
 
         for (sample_id, mut channel_samples) in buffer.iter_samples().enumerate() {
+            // the tuning has to be checked before pulling the next values, or its last step would be missed
+            let tune_changed = self.params.coarse_tune.smoothed.is_smoothing()
+                || self.params.fine_tune.smoothed.is_smoothing();
+            self.sampler.smoothed = SmoothedValues::next(&self.params);
             'midi_events: loop {
                 match next_event {
                     Some(event) if event.timing() == sample_id as u32 => match event {
-                        NoteEvent::NoteOn { note, .. } => {
-                            self.note_on(note);
+                        NoteEvent::NoteOn { note, velocity, .. } => {
+                            self.note_on(note, velocity);
                         }
                         NoteEvent::NoteOff { note, .. } => {
                             self.note_off(note);
                         }
+                        // times 2 because the smoother runs at the internal sample rate
+                        NoteEvent::MidiPitchBend { value, .. } => self
+                            .pitchbend
+                            .set_target(self.sample_rate * 2., (value - 0.5) * 2.),
+                        NoteEvent::MidiCC { cc, value, .. } => match cc {
+                            SUSTAIN_CC => self.set_sustain(value >= 0.5),
+                            SOSTENUTO_CC => self.set_sostenuto(value >= 0.5),
+                            _ => (),
+                        },
                         _ => (),
                     },
                     _ => break 'midi_events,
@@ -213,15 +553,34 @@ impl Plugin for Plug<'static> {
             let mut out = [0.; 2];
             for _i in 0..2 {
                 let mut voices_out = [0.; 2];
+                let bend_changed = self.pitchbend.is_smoothing();
+                if bend_changed {
+                    self.sampler.pitchbend = self.pitchbend.next();
+                }
                 for i in 0..self.voices.len() {
-                    if self.voices[i].is_on {
-                        let sampler_out = self.sampler.process(
-                            i,
-                            &mut self.voices[i].sampler_phase,
+                    if self.voices[i].is_active() {
+                        if self.voices[i].update_pitch() || bend_changed || tune_changed {
+                            self.sampler
+                                .voice_pitch_changed(&self.params, &mut self.voices[i]);
+                        }
+                        let sampler_out = self.sampler.process(&mut self.voices[i], &self.params);
+                        let voice = &mut self.voices[i];
+                        let sampler_out = voice.filter.process(
+                            sampler_out,
                             &self.params,
+                            &self.sampler.smoothed,
+                            voice.current_notepitch + self.sampler.bend(&self.params),
+                            self.sample_rate,
                         );
-                        voices_out[0] += sampler_out[0];
-                        voices_out[1] += sampler_out[1];
+                        let env = self.voices[i].vol_env.next(&self.params, self.sample_rate);
+                        voices_out[0] += sampler_out[0] * env;
+                        voices_out[1] += sampler_out[1] * env;
+                        // a voice that has played through its sample has nothing more to say
+                        if self.voices[i].is_done {
+                            self.voices[i].stop();
+                        } else if self.voices[i].out_of_range {
+                            self.voices[i].declick(self.sample_rate);
+                        }
                     }
                 }
                 // Filter the output of the samplers to avoid aliasing
@@ -259,3 +618,34 @@ nih_export_clap!(Plug<'static>);
 // }
 
 // nih_export_vst3!(Plug);
+
+#[test]
+fn note_off_releases_every_layer() {
+    // two overlapping zones of one press of the key, a later press of it, and another key
+    let mut voices = vec![Voice::new(); 4];
+    for (voice, (note, press)) in voices.iter_mut().zip([(60, 1), (60, 1), (60, 2), (62, 3)]) {
+        voice.is_on = true;
+        voice.current_note = note;
+        voice.press = press;
+    }
+    let release = |voices: &mut [Voice]| {
+        let press = held_press(voices, 60).unwrap();
+        for voice in voices
+            .iter_mut()
+            .filter(|voice| voice.is_held_by(60, press))
+        {
+            voice.release();
+        }
+    };
+    let is_on = |voices: &[Voice]| {
+        voices
+            .iter()
+            .map(|voice| voice.is_on)
+            .collect::<Vec<bool>>()
+    };
+    release(&mut voices);
+    assert_eq!(is_on(&voices), vec![false, false, true, true]);
+    release(&mut voices);
+    assert_eq!(is_on(&voices), vec![false, false, false, true]);
+    assert_eq!(held_press(&voices, 60), None);
+}
@@ -0,0 +1,133 @@
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use crate::parameters::SamplerParams;
+use crate::sampler::Keymap;
+
+// how many messages can be queued for the loader thread before the audio thread has to back off
+const QUEUE_SIZE: usize = 8;
+
+enum LoaderMessage {
+    // decode the sample currently named in the params and preprocess it
+    Load,
+    // sample data the audio thread is done with. Deallocating it is the loader's job, not the audio thread's
+    Dispose(Box<Keymap<'static>>),
+}
+
+/// What became of a load request
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LoadRequest {
+    Queued,
+    // the queue is full, try again later
+    Full,
+    // the loader thread has stopped. It has to be restarted with `restart_if_stopped` before anything can be loaded
+    Stopped,
+}
+
+/// Handle to the background thread that decodes samples and computes the mip maps and coefficients.
+/// All communication goes through bounded channels, so none of the calls made from the audio thread allocate or block
+pub struct SampleLoader {
+    params: Arc<SamplerParams>,
+    thread: JoinHandle<()>,
+    sender: SyncSender<LoaderMessage>,
+    receiver: Receiver<Box<Keymap<'static>>>,
+    // old data that couldn't be handed back to the loader yet
+    pending_dispose: Option<Box<Keymap<'static>>>,
+}
+
+impl SampleLoader {
+    pub fn new(params: Arc<SamplerParams>) -> SampleLoader {
+        let (thread, sender, receiver) = spawn_loader(params.clone());
+        SampleLoader {
+            params,
+            thread,
+            sender,
+            receiver,
+            pending_dispose: None,
+        }
+    }
+    /// Starts a new loader thread if the old one has stopped. Returns true if it had to.
+    /// This allocates, so it must not be called from the audio thread
+    pub fn restart_if_stopped(&mut self) -> bool {
+        if !self.thread.is_finished() {
+            return false;
+        }
+        println!("The sample loader thread stopped, restarting it");
+        let (thread, sender, receiver) = spawn_loader(self.params.clone());
+        self.thread = thread;
+        self.sender = sender;
+        self.receiver = receiver;
+        true
+    }
+    /// Asks the loader thread to load the current sample
+    pub fn request_load(&self) -> LoadRequest {
+        match self.sender.try_send(LoaderMessage::Load) {
+            Ok(()) => LoadRequest::Queued,
+            Err(TrySendError::Full(_)) => LoadRequest::Full,
+            Err(TrySendError::Disconnected(_)) => LoadRequest::Stopped,
+        }
+    }
+    /// Returns the newest finished sample, if the loader has one ready
+    pub fn try_receive(&mut self) -> Option<Box<Keymap<'static>>> {
+        // retry handing back old data before anything else
+        if let Some(data) = self.pending_dispose.take() {
+            self.dispose(data);
+        }
+        self.receiver.try_recv().ok()
+    }
+    /// Hands sample data back to the loader thread so it gets deallocated there
+    pub fn dispose(&mut self, data: Box<Keymap<'static>>) {
+        let data = match self.sender.try_send(LoaderMessage::Dispose(data)) {
+            // a stopped loader gets the data once it's restarted
+            Err(TrySendError::Full(LoaderMessage::Dispose(data)))
+            | Err(TrySendError::Disconnected(LoaderMessage::Dispose(data))) => data,
+            _ => return,
+        };
+        match self.pending_dispose {
+            None => self.pending_dispose = Some(data),
+            // leaking is better than deallocating on the audio thread
+            Some(_) => mem::forget(data),
+        }
+    }
+}
+
+fn spawn_loader(
+    params: Arc<SamplerParams>,
+) -> (
+    JoinHandle<()>,
+    SyncSender<LoaderMessage>,
+    Receiver<Box<Keymap<'static>>>,
+) {
+    let (sender, loader_receiver) = mpsc::sync_channel(QUEUE_SIZE);
+    let (loader_sender, receiver) = mpsc::sync_channel(1);
+    let thread = thread::Builder::new()
+        .name(String::from("sampler-rs loader"))
+        .spawn(move || {
+            // the loop ends when the plugin, and with it the SampleLoader, is dropped
+            while let Ok(message) = loader_receiver.recv() {
+                match message {
+                    LoaderMessage::Load => {
+                        // a broken file shouldn't be able to take the thread down with it
+                        let data = match panic::catch_unwind(AssertUnwindSafe(|| {
+                            Keymap::from_params(&params)
+                        })) {
+                            Ok(data) => Box::new(data),
+                            Err(_) => {
+                                println!("Error in sample loading: the loader panicked");
+                                continue;
+                            }
+                        };
+                        if loader_sender.send(data).is_err() {
+                            break;
+                        }
+                    }
+                    LoaderMessage::Dispose(data) => drop(data),
+                }
+            }
+        })
+        .expect("failed to spawn the sample loader thread");
+    (thread, sender, receiver)
+}
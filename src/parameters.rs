@@ -5,6 +5,8 @@ use nih_plug::prelude::*;
 
 use std::path::PathBuf;
 const SMOOTHING_TIME: f32 = 20.;
+/// The voices are all allocated up front, so this is the most the polyphony param can go up to
+pub const MAX_VOICES: usize = 64;
 #[derive(Params)]
 pub struct SamplerParams {
     // #[persist = "sample_name"]
@@ -12,6 +14,20 @@ pub struct SamplerParams {
 
     #[id = "mono"]
     pub mono: EnumParam<OnOff>,
+    #[id = "legato"]
+    pub legato: EnumParam<OnOff>,
+    #[id = "note priority"]
+    pub note_priority: EnumParam<NotePriority>,
+    #[id = "voices"]
+    pub voices: IntParam,
+    #[id = "voice stealing"]
+    pub steal_mode: EnumParam<StealMode>,
+    #[id = "trigger mode"]
+    pub trigger_mode: EnumParam<TriggerMode>,
+    #[id = "retrigger"]
+    pub retrigger: EnumParam<OnOff>,
+    #[id = "round robin"]
+    pub round_robin: EnumParam<RoundRobin>,
 
     pub pitch_changed: Arc<AtomicBool>,
     #[id = "sampler root"]
@@ -29,8 +45,17 @@ pub struct SamplerParams {
     pub pos: FloatParam,
     #[id = "sampler end position"]
     pub end: FloatParam,
+    #[id = "sampler reverse"]
+    pub reverse: EnumParam<OnOff>,
     #[id = "sampler looping"]
-    pub is_looping: EnumParam<OnOff>,
+    pub loop_mode: EnumParam<LoopMode>,
+    #[id = "sampler loop start"]
+    pub loop_start: FloatParam,
+    #[id = "sampler loop end"]
+    pub loop_end: FloatParam,
+    /// in ms
+    #[id = "sampler loop crossfade"]
+    pub loop_xfade: FloatParam,
     // the audio data for the sampler
     pub source: RwLock<Vec<f32>>,
     pub source_changed: AtomicBool,
@@ -42,6 +67,41 @@ pub struct SamplerParams {
 
     #[id = "sampler keytrack"]
     pub keytrack: EnumParam<OnOff>,
+    /// pitch bend range in semitones
+    #[id = "bend up"]
+    pub bend_up: IntParam,
+    #[id = "bend down"]
+    pub bend_down: IntParam,
+
+    #[id = "amp attack"]
+    pub attack: FloatParam,
+    #[id = "amp decay"]
+    pub decay: FloatParam,
+    #[id = "amp sustain"]
+    pub sustain: FloatParam,
+    #[id = "amp release"]
+    pub release: FloatParam,
+
+    #[id = "velocity amount"]
+    pub vel_amount: FloatParam,
+    #[id = "velocity curve"]
+    pub vel_curve: EnumParam<VelCurve>,
+
+    #[id = "filter type"]
+    pub filter_type: EnumParam<FilterType>,
+    #[id = "filter slope"]
+    pub filter_slope: EnumParam<FilterSlope>,
+    #[id = "filter cutoff"]
+    pub cutoff: FloatParam,
+    #[id = "filter resonance"]
+    pub resonance: FloatParam,
+    #[id = "filter keytrack"]
+    pub filter_keytrack: FloatParam,
+
+    #[id = "glide time"]
+    pub glide_time: FloatParam,
+    #[id = "glide mode"]
+    pub glide_mode: EnumParam<GlideMode>,
 }
 impl SamplerParams {
     pub(crate) fn get_sample(&self) -> Vec<f32> {
@@ -98,8 +158,22 @@ impl Default for SamplerParams {
     fn default() -> Self {
         let pitch_changed = Arc::new(AtomicBool::new(false));
         let a = Self {
-            sample_name: RwLock::new(SampleName(PathBuf::from("Hard kick 1.wav"))),
+            sample_name: RwLock::new(SampleName(PathBuf::from("Hard kick 1.wav"), None)),
             mono: EnumParam::new("Mono", OnOff::Off),
+            legato: EnumParam::new("Legato", OnOff::Off),
+            note_priority: EnumParam::new("Note Priority", NotePriority::Last),
+            voices: IntParam::new(
+                "Voices",
+                8,
+                IntRange::Linear {
+                    min: 1,
+                    max: MAX_VOICES as i32,
+                },
+            ),
+            steal_mode: EnumParam::new("Voice Stealing", StealMode::Oldest),
+            trigger_mode: EnumParam::new("Trigger Mode", TriggerMode::Gate),
+            retrigger: EnumParam::new("Retrigger", OnOff::Off),
+            round_robin: EnumParam::new("Round Robin", RoundRobin::Sequential),
             pitch_changed: pitch_changed.clone(),
             sample_sample_rate: AtomicF32::new(0.),
 
@@ -147,7 +221,7 @@ impl Default for SamplerParams {
             )
             .with_smoother(SmoothingStyle::Linear(SMOOTHING_TIME))
             .with_value_to_string(formatters::v2s_f32_rounded(2)),
-            end: FloatParam::new("Sampler End", 0.0, FloatRange::Linear { min: 0., max: 1. })
+            end: FloatParam::new("Sampler End", 1.0, FloatRange::Linear { min: 0., max: 1. })
                 .with_smoother(SmoothingStyle::Linear(SMOOTHING_TIME))
                 .with_value_to_string(formatters::v2s_f32_rounded(2)),
 
@@ -155,20 +229,199 @@ impl Default for SamplerParams {
                 let pitch_changed = pitch_changed.clone();
                 move |_| pitch_changed.set_release(true)
             })),
+            bend_up: IntParam::new("Bend Up", 2, IntRange::Linear { min: 0, max: 48 })
+                .with_unit(" st")
+                .with_callback(Arc::new({
+                    let pitch_changed = pitch_changed.clone();
+                    move |_| pitch_changed.set_release(true)
+                })),
+            bend_down: IntParam::new("Bend Down", 2, IntRange::Linear { min: 0, max: 48 })
+                .with_unit(" st")
+                .with_callback(Arc::new({
+                    let pitch_changed = pitch_changed.clone();
+                    move |_| pitch_changed.set_release(true)
+                })),
 
             source: RwLock::new(vec![0.; 2048]),
             source_changed: AtomicBool::new(false),
             is_on: EnumParam::new("Sampler On/off", OnOff::On),
             sample_mono: AtomicBool::new(true),
-            is_looping: EnumParam::new("Sampler Loop", OnOff::Off),
+            reverse: EnumParam::new("Sampler Reverse", OnOff::Off),
+            loop_mode: EnumParam::new("Sampler Loop Mode", LoopMode::Off),
+            loop_start: FloatParam::new(
+                "Sampler Loop Start",
+                0.0,
+                FloatRange::Linear { min: 0., max: 0.99 },
+            )
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            loop_end: FloatParam::new(
+                "Sampler Loop End",
+                1.0,
+                FloatRange::Linear { min: 0., max: 1. },
+            )
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            loop_xfade: FloatParam::new(
+                "Sampler Loop Crossfade",
+                0.,
+                FloatRange::Skewed {
+                    min: 0.,
+                    max: 1000.,
+                    factor: FloatRange::skew_factor(-2.),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            attack: FloatParam::new("Attack", 1., env_time_range())
+                .with_unit(" ms")
+                .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            decay: FloatParam::new("Decay", 100., env_time_range())
+                .with_unit(" ms")
+                .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            sustain: FloatParam::new("Sustain", 1., FloatRange::Linear { min: 0., max: 1. })
+                .with_value_to_string(formatters::v2s_f32_percentage(1)),
+            release: FloatParam::new("Release", 10., env_time_range())
+                .with_unit(" ms")
+                .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            vel_amount: FloatParam::new("Velocity", 1., FloatRange::Linear { min: 0., max: 1. })
+                .with_value_to_string(formatters::v2s_f32_percentage(1)),
+            vel_curve: EnumParam::new("Velocity Curve", VelCurve::Linear),
+
+            filter_type: EnumParam::new("Filter Type", FilterType::Off),
+            filter_slope: EnumParam::new("Filter Slope", FilterSlope::Db12),
+            cutoff: FloatParam::new(
+                "Cutoff",
+                20000.,
+                FloatRange::Skewed {
+                    min: 20.,
+                    max: 20000.,
+                    factor: FloatRange::skew_factor(-2.),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(SMOOTHING_TIME))
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+            resonance: FloatParam::new("Resonance", 0., FloatRange::Linear { min: 0., max: 1. })
+                .with_smoother(SmoothingStyle::Linear(SMOOTHING_TIME))
+                .with_value_to_string(formatters::v2s_f32_percentage(1)),
+            filter_keytrack: FloatParam::new(
+                "Filter Keytrack",
+                0.,
+                FloatRange::Linear { min: 0., max: 1. },
+            )
+            .with_value_to_string(formatters::v2s_f32_percentage(1)),
+
+            glide_time: FloatParam::new(
+                "Glide Time",
+                0.,
+                FloatRange::Skewed {
+                    min: 0.,
+                    max: 5000.,
+                    factor: FloatRange::skew_factor(-2.),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            glide_mode: EnumParam::new("Glide Mode", GlideMode::ConstantTime),
         };
         a.load_sample();
         a
     }
 }
 
+// range for the envelope times, in ms
+fn env_time_range() -> FloatRange {
+    FloatRange::Skewed {
+        min: 0.,
+        max: 10000.,
+        factor: FloatRange::skew_factor(-2.),
+    }
+}
+
 #[derive(Enum, Debug, PartialEq)]
 pub enum OnOff {
     Off,
     On,
 }
+
+/// How note velocity maps to volume
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+pub enum VelCurve {
+    Linear,
+    Exponential,
+    Logarithmic,
+    // every note plays at full volume
+    Fixed,
+}
+
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+pub enum GlideMode {
+    // every glide takes the glide time
+    ConstantTime,
+    // the glide time is how long it takes to glide an octave
+    ConstantRate,
+}
+
+/// Which voice gets cut off when a note comes in and all voices are busy
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+pub enum StealMode {
+    Oldest,
+    Quietest,
+    Lowest,
+    Highest,
+    // steal the quietest of the voices in their release stage, if there are any
+    PreferReleased,
+}
+
+/// Whether letting go of a key releases its voices
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+pub enum TriggerMode {
+    // the voice is released on note off
+    Gate,
+    // note offs are ignored and the voice plays until the end of the sample, like a drum machine
+    OneShot,
+}
+
+// how zones sharing a key take turns
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+pub enum RoundRobin {
+    Sequential,
+    // random, but never the same zone twice in a row
+    Random,
+}
+
+/// Which of the held notes plays in mono mode
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+pub enum NotePriority {
+    Last,
+    First,
+    Lowest,
+    Highest,
+}
+
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+pub enum FilterType {
+    Off,
+    LowPass,
+    HighPass,
+    BandPass,
+    Notch,
+}
+
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+pub enum FilterSlope {
+    Db12,
+    Db24,
+}
+
+// Off comes first and Forward second, so automation and presets from when looping was on/off still work
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+pub enum LoopMode {
+    Off,
+    Forward,
+    // alternates between playing the loop forwards and backwards
+    PingPong,
+    // plays the loop backwards once it's reached
+    Reverse,
+}
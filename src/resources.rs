@@ -1,74 +1,270 @@
-use std::{fs, io, path::PathBuf};
-const I24_MAX: i32 = 2_i32.pow(23) - 1;
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Read},
+    path::{Path, PathBuf},
+};
 // use dirs;
-use hound::{self, WavReader};
+use claxon::FlacReader;
+use hound::{self, SampleFormat};
 use lazy_static::lazy_static;
+use lewton::{inside_ogg::OggStreamReader, samples::InterleavedSamples, VorbisError};
 use std::fmt;
 
+use crate::sf2::SoundFont;
+
+mod aiff;
+mod wav;
+
 const FOLDER_PATH: &str = r"Documents/sampler-rs/";
 
-// SampleName is just a wrapper around a PathBuf, so we can use it with Vizia (needs to impl a Data trait to be lensed)
+// SampleName is just a wrapper around a PathBuf, so we can use it with Vizia (needs to impl a Data trait to be lensed).
+// Sound fonts hold many instruments, so for those it also says which preset to play
 #[derive(Clone, Debug)]
-pub struct SampleName(pub PathBuf);
+pub struct SampleName(pub PathBuf, pub Option<PresetName>);
+
+#[derive(Clone, Debug)]
+pub struct PresetName {
+    // the preset's position in the sound font
+    pub index: usize,
+    pub name: String,
+}
 
 impl SampleName {
     //
-    pub fn load_sample(self) -> Result<Sample, hound::Error> {
-        let path = dirs::home_dir()
+    pub fn load_sample(self) -> Result<Sample, SampleError> {
+        load_audio(&self.path())
+    }
+    // where the sample is on disk
+    pub fn path(&self) -> PathBuf {
+        dirs::home_dir()
             .unwrap()
-            .join(PathBuf::from(FOLDER_PATH).join("samples").join(self.0));
-        let mut reader = WavReader::open(path)?;
-        let spec = reader.spec();
-        if spec.bits_per_sample == 32 {
-            let read = reader.samples().collect::<Result<Vec<f32>, hound::Error>>();
-            if let Ok(samples) = read {
-                // samples.push(spec.sample_rate as f32);
-                return Ok(Sample {
-                    data: samples,
-                    sample_rate: spec.sample_rate as f32,
-                    channels: spec.channels as usize,
-                });
-            } else {
-                return Err(hound::Error::FormatError("Failed reading samples"));
+            .join(PathBuf::from(FOLDER_PATH).join("samples").join(&self.0))
+    }
+    // sfz instruments are loaded as a whole keymap instead of a single sample
+    pub fn is_sfz(&self) -> bool {
+        match self.0.extension() {
+            Some(extension) => extension.eq_ignore_ascii_case("sfz"),
+            None => false,
+        }
+    }
+}
+
+/// The kinds of files that can go in the samples folder
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    Wav,
+    Flac,
+    Aiff,
+    Vorbis,
+    SoundFont,
+}
+
+impl Format {
+    // extensions can't be trusted, so this looks at the first bytes of the file instead
+    pub fn sniff(path: &Path) -> io::Result<Option<Format>> {
+        let mut header = Vec::with_capacity(12);
+        File::open(path)?.take(12).read_to_end(&mut header)?;
+        let format = if header.starts_with(b"fLaC") {
+            Some(Format::Flac)
+        } else if header.starts_with(b"OggS") {
+            Some(Format::Vorbis)
+        } else if header.len() == 12 {
+            match (&header[..4], &header[8..]) {
+                (b"RIFF", b"WAVE") => Some(Format::Wav),
+                (b"RIFF", b"sfbk") => Some(Format::SoundFont),
+                (b"FORM", b"AIFF") | (b"FORM", b"AIFC") => Some(Format::Aiff),
+                _ => None,
             }
-        } else if spec.bits_per_sample == 16 {
-            let read = reader.samples().collect::<Result<Vec<i16>, hound::Error>>();
-            if let Ok(samples) = read {
-                let float_samples: Vec<f32> = samples
-                    .iter()
-                    .map(|val| *val as f32 / i16::MAX as f32)
-                    .collect();
-                // float_samples.push(spec.sample_rate as f32);
-                return Ok(Sample {
-                    data: float_samples,
-                    sample_rate: spec.sample_rate as f32,
-                    channels: spec.channels as usize,
-                });
-            } else {
-                return Err(hound::Error::FormatError("Failed reading samples"));
+        } else {
+            None
+        };
+        Ok(format)
+    }
+}
+
+#[derive(Debug)]
+pub enum SampleError {
+    Io(io::Error),
+    Wav(hound::Error),
+    Flac(claxon::Error),
+    Vorbis(VorbisError),
+    Aiff(&'static str),
+    // a wav sample type we can't decode, with its bit depth
+    WavSamples(SampleFormat, u16),
+    // a compressed wav, with its format tag
+    WavCodec(u16),
+    // the contents don't look like any format we can decode
+    UnknownFormat,
+}
+
+impl From<io::Error> for SampleError {
+    fn from(err: io::Error) -> Self {
+        SampleError::Io(err)
+    }
+}
+impl From<hound::Error> for SampleError {
+    fn from(err: hound::Error) -> Self {
+        SampleError::Wav(err)
+    }
+}
+impl From<claxon::Error> for SampleError {
+    fn from(err: claxon::Error) -> Self {
+        SampleError::Flac(err)
+    }
+}
+impl From<VorbisError> for SampleError {
+    fn from(err: VorbisError) -> Self {
+        SampleError::Vorbis(err)
+    }
+}
+
+impl fmt::Display for SampleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SampleError::Io(err) => write!(f, "{err}"),
+            SampleError::Wav(err) => write!(f, "wav: {err}"),
+            SampleError::Flac(err) => write!(f, "flac: {err}"),
+            SampleError::Vorbis(err) => write!(f, "ogg vorbis: {err}"),
+            SampleError::Aiff(err) => write!(f, "aiff: {err}"),
+            SampleError::WavSamples(SampleFormat::Int, bits) => {
+                write!(f, "wav: {bits} bit integer samples aren't supported")
             }
-        } else if spec.bits_per_sample == 24 {
-            let read = reader.samples().collect::<Result<Vec<i32>, hound::Error>>();
-            if let Ok(samples) = read {
-                // println!("max val: {}", samples.iter().max().unwrap());
-                let float_samples: Vec<f32> = samples
-                    .iter()
-                    .map(|val| *val as f32 / I24_MAX as f32)
-                    .collect();
-                // float_samples.push(spec.sample_rate as f32);
-                return Ok(Sample {
-                    data: float_samples,
-                    sample_rate: spec.sample_rate as f32,
-                    channels: spec.channels as usize,
-                });
-            } else {
-                return Err(hound::Error::FormatError("Failed reading samples"));
+            SampleError::WavSamples(SampleFormat::Float, bits) => {
+                write!(f, "wav: {bits} bit float samples aren't supported")
             }
-        } else {
-            return Err(hound::Error::FormatError("unsupported sample type"));
+            SampleError::WavCodec(tag) => {
+                write!(f, "wav: compressed format {tag:#06x} isn't supported")
+            }
+            SampleError::UnknownFormat => write!(f, "not a wav, flac, aiff or ogg vorbis file"),
+        }
+    }
+}
+
+/// Decodes an audio file, picking the decoder from what's in it
+pub fn load_audio(path: &Path) -> Result<Sample, SampleError> {
+    match Format::sniff(path)? {
+        Some(Format::Wav) => wav::load_wav(path),
+        Some(Format::Flac) => load_flac(path),
+        Some(Format::Aiff) => aiff::load_aiff(path),
+        Some(Format::Vorbis) => load_vorbis(path),
+        Some(Format::SoundFont) | None => Err(SampleError::UnknownFormat),
+    }
+}
+
+fn load_flac(path: &Path) -> Result<Sample, SampleError> {
+    let mut reader = FlacReader::open(path)?;
+    let info = reader.streaminfo();
+    // flac samples are integers of any bit depth up to 32
+    let scale = 1. / (1_i64 << (info.bits_per_sample - 1)) as f32;
+    let data = reader
+        .samples()
+        .map(|val| val.map(|val| val as f32 * scale))
+        .collect::<Result<Vec<f32>, claxon::Error>>()?;
+    Ok(Sample {
+        data,
+        sample_rate: info.sample_rate as f32,
+        channels: info.channels as usize,
+    })
+}
+
+fn load_vorbis(path: &Path) -> Result<Sample, SampleError> {
+    let mut reader = OggStreamReader::new(BufReader::new(File::open(path)?))?;
+    let mut data = Vec::new();
+    while let Some(packet) = reader.read_dec_packet_generic::<InterleavedSamples<f32>>()? {
+        data.extend(packet.samples);
+    }
+    Ok(Sample {
+        data,
+        sample_rate: reader.ident_hdr.audio_sample_rate as f32,
+        channels: reader.ident_hdr.audio_channels as usize,
+    })
+}
+
+// byte order of the chunk sizes and sample points. RIFF (wav) is little endian, IFF (aiff) is big endian
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Endian {
+    Big,
+    Little,
+}
+
+impl Endian {
+    fn read_u32(self, bytes: &[u8]) -> u32 {
+        let bytes = bytes[..4].try_into().unwrap();
+        match self {
+            Endian::Big => u32::from_be_bytes(bytes),
+            Endian::Little => u32::from_le_bytes(bytes),
         }
     }
 }
+
+// the (id, contents) of every chunk in a RIFF or IFF file, after the 12 byte file header.
+// The last chunk is cut off at the end of the file, in case the file was cut short
+fn chunks(bytes: &[u8], endian: Endian) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut pos = 12;
+    std::iter::from_fn(move || {
+        if pos + 8 > bytes.len() {
+            return None;
+        }
+        let size = endian.read_u32(&bytes[pos + 4..pos + 8]) as usize;
+        let start = pos + 8;
+        let end = start.saturating_add(size).min(bytes.len());
+        let chunk = (&bytes[pos..pos + 4], &bytes[start..end]);
+        // chunks are padded to an even length
+        pos = start.saturating_add(size + size % 2);
+        Some(chunk)
+    })
+}
+
+// converts integer sample points that take up `width` bytes (1 to 4) to floats.
+// They're scaled by the width, so every bit depth ends up in -1..1
+fn ints_to_f32(data: &[u8], width: usize, endian: Endian, unsigned: bool) -> Vec<f32> {
+    data.chunks_exact(width)
+        .map(|b| {
+            // the point goes in the top bytes of an i32, so the sign comes along
+            let mut word = [0; 4];
+            match endian {
+                Endian::Big => word[..width].copy_from_slice(b),
+                Endian::Little => {
+                    for (i, &byte) in b.iter().enumerate() {
+                        word[width - 1 - i] = byte;
+                    }
+                }
+            }
+            if unsigned {
+                word[0] ^= 0x80;
+            }
+            i32::from_be_bytes(word) as f32 / 2_f32.powi(31)
+        })
+        .collect()
+}
+
+// builds a RIFF or IFF file out of chunks, for testing the decoders
+#[cfg(test)]
+fn build_chunks(
+    header: &[u8; 4],
+    form: &[u8; 4],
+    chunks: &[(&[u8; 4], &[u8])],
+    endian: Endian,
+) -> Vec<u8> {
+    let size = |len: usize| match endian {
+        Endian::Big => (len as u32).to_be_bytes(),
+        Endian::Little => (len as u32).to_le_bytes(),
+    };
+    let mut body = form.to_vec();
+    for (id, chunk) in chunks {
+        body.extend_from_slice(*id);
+        body.extend_from_slice(&size(chunk.len()));
+        body.extend_from_slice(chunk);
+        if chunk.len() % 2 == 1 {
+            body.push(0);
+        }
+    }
+    let mut file = header.to_vec();
+    file.extend_from_slice(&size(body.len()));
+    file.extend(body);
+    file
+}
+
 /// Helper struct to share details about the data from the wav file
 pub struct Sample {
     pub data: Vec<f32>,
@@ -77,12 +273,33 @@ pub struct Sample {
 }
 
 pub fn samples() -> io::Result<Vec<SampleName>> {
-    SAMPLES
-        .iter()
-        .map(|builtin| Ok(builtin.clone()))
-        // .chain(preset_table_dir_tables())
-        .map(|path_buf| path_buf.map(SampleName))
-        .collect()
+    let mut samples = Vec::new();
+    for path_buf in SAMPLES.iter() {
+        let sample = SampleName(path_buf.clone(), None);
+        // sfz files are plain text, so there's nothing to sniff
+        if sample.is_sfz() {
+            samples.push(sample);
+            continue;
+        }
+        match Format::sniff(&sample.path()) {
+            // every preset of a sound font gets listed on its own
+            Ok(Some(Format::SoundFont)) => match SoundFont::preset_names(&sample.path()) {
+                Ok(names) => {
+                    for (index, name) in names.into_iter().enumerate() {
+                        samples.push(SampleName(
+                            path_buf.clone(),
+                            Some(PresetName { index, name }),
+                        ));
+                    }
+                }
+                Err(err) => println!("Error in reading sound font: {sample}. {err}"),
+            },
+            Ok(Some(_)) => samples.push(sample),
+            // folders and files we can't play are left out
+            Ok(None) | Err(_) => (),
+        }
+    }
+    Ok(samples)
 }
 
 lazy_static! {
@@ -107,7 +324,10 @@ impl fmt::Display for SampleName {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut string = format!("{:?}", self.0.file_stem().unwrap());
         string = string.replace('"', "");
-        write!(f, "{}", string)
+        match &self.1 {
+            Some(preset) => write!(f, "{}: {}", string, preset.name),
+            None => write!(f, "{}", string),
+        }
     }
 }
 
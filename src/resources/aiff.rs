@@ -0,0 +1,158 @@
+//! AIFF and AIFC decoding. It's a simple chunked format, so it's read by hand
+use std::{fs, path::Path};
+
+use super::{chunks, ints_to_f32, Endian, Sample, SampleError};
+
+pub fn load_aiff(path: &Path) -> Result<Sample, SampleError> {
+    let bytes = fs::read(path)?;
+    parse_aiff(&bytes)
+}
+
+// how the sample points are stored in the SSND chunk
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Encoding {
+    BigEndian,
+    // AIFC's "sowt", written by a lot of mac software
+    LittleEndian,
+    Float32,
+    Float64,
+}
+
+// the COMM chunk
+struct Common {
+    channels: usize,
+    frames: usize,
+    bits: usize,
+    sample_rate: f64,
+    encoding: Encoding,
+}
+
+impl Common {
+    fn parse(chunk: &[u8], compressed: bool) -> Result<Common, SampleError> {
+        if chunk.len() < 18 {
+            return Err(SampleError::Aiff("the COMM chunk is too short"));
+        }
+        let encoding = if compressed {
+            match chunk.get(18..22) {
+                Some(b"NONE") | Some(b"twos") => Encoding::BigEndian,
+                Some(b"sowt") => Encoding::LittleEndian,
+                Some(b"fl32") | Some(b"FL32") => Encoding::Float32,
+                Some(b"fl64") | Some(b"FL64") => Encoding::Float64,
+                Some(_) => return Err(SampleError::Aiff("unsupported AIFC compression type")),
+                None => return Err(SampleError::Aiff("the COMM chunk is too short")),
+            }
+        } else {
+            Encoding::BigEndian
+        };
+        Ok(Common {
+            channels: u16::from_be_bytes([chunk[0], chunk[1]]) as usize,
+            frames: u32::from_be_bytes(chunk[2..6].try_into().unwrap()) as usize,
+            bits: u16::from_be_bytes([chunk[6], chunk[7]]) as usize,
+            sample_rate: extended_to_f64(&chunk[8..18]),
+            encoding,
+        })
+    }
+}
+
+fn parse_aiff(bytes: &[u8]) -> Result<Sample, SampleError> {
+    if bytes.len() < 12 || &bytes[..4] != b"FORM" {
+        return Err(SampleError::Aiff("missing the FORM header"));
+    }
+    let compressed = match &bytes[8..12] {
+        b"AIFF" => false,
+        b"AIFC" => true,
+        _ => return Err(SampleError::Aiff("not an AIFF or AIFC file")),
+    };
+    let mut common = None;
+    let mut sound = None;
+    for (id, chunk) in chunks(bytes, Endian::Big) {
+        match id {
+            b"COMM" => common = Some(Common::parse(chunk, compressed)?),
+            b"SSND" => sound = Some(chunk),
+            _ => (),
+        }
+    }
+    let common = common.ok_or(SampleError::Aiff("missing the COMM chunk"))?;
+    let sound = sound.ok_or(SampleError::Aiff("missing the SSND chunk"))?;
+    if common.channels == 0 {
+        return Err(SampleError::Aiff("the file has no channels"));
+    }
+    if common.sample_rate <= 0. || !common.sample_rate.is_finite() {
+        return Err(SampleError::Aiff("the sample rate is 0 or invalid"));
+    }
+    if sound.len() < 8 {
+        return Err(SampleError::Aiff("the SSND chunk is too short"));
+    }
+    // the audio can start a bit into the chunk
+    let offset = u32::from_be_bytes(sound[..4].try_into().unwrap()) as usize;
+    let mut data = decode(sound.get(8 + offset..).unwrap_or(&[]), &common)?;
+    // a chunk that was cut short can end partway through a frame, which would throw off the channels
+    let frames = common.frames.min(data.len() / common.channels);
+    data.truncate(frames * common.channels);
+    Ok(Sample {
+        data,
+        channels: common.channels,
+        sample_rate: common.sample_rate as f32,
+    })
+}
+
+fn decode(data: &[u8], common: &Common) -> Result<Vec<f32>, SampleError> {
+    let samples = match common.encoding {
+        Encoding::Float32 => data
+            .chunks_exact(4)
+            .map(|b| f32::from_be_bytes(b.try_into().unwrap()))
+            .collect(),
+        Encoding::Float64 => data
+            .chunks_exact(8)
+            .map(|b| f64::from_be_bytes(b.try_into().unwrap()) as f32)
+            .collect(),
+        encoding => {
+            // every sample point takes up whole bytes
+            let width = common.bits.div_ceil(8);
+            if !(1..=4).contains(&width) {
+                return Err(SampleError::Aiff("unsupported bit depth"));
+            }
+            let endian = match encoding {
+                Encoding::LittleEndian => Endian::Little,
+                _ => Endian::Big,
+            };
+            ints_to_f32(data, width, endian, false)
+        }
+    };
+    Ok(samples)
+}
+
+// the sample rate is stored as an 80 bit extended precision float
+fn extended_to_f64(bytes: &[u8]) -> f64 {
+    let exponent = (u16::from_be_bytes([bytes[0], bytes[1]]) & 0x7fff) as i32;
+    let mantissa = u64::from_be_bytes(bytes[2..10].try_into().unwrap());
+    if exponent == 0 && mantissa == 0 {
+        return 0.;
+    }
+    mantissa as f64 * 2_f64.powi(exponent - 16383 - 63)
+}
+
+#[test]
+fn parse_aifc() {
+    // 44.1 kHz as an extended float
+    let rate = [0x40, 0x0e, 0xac, 0x44, 0, 0, 0, 0, 0, 0];
+    let mut comm = vec![0, 2, 0, 0, 0, 2, 0, 16];
+    comm.extend_from_slice(&rate);
+    comm.extend_from_slice(b"sowt");
+    comm.extend_from_slice(&[0, 0]);
+    let mut ssnd = vec![0; 8];
+    for point in [i16::MIN, 0x4000, 0, -0x4000] {
+        ssnd.extend_from_slice(&point.to_le_bytes());
+    }
+    let file = super::build_chunks(
+        b"FORM",
+        b"AIFC",
+        &[(b"COMM", &comm), (b"SSND", &ssnd)],
+        Endian::Big,
+    );
+
+    let sample = parse_aiff(&file).unwrap();
+    assert_eq!(sample.channels, 2);
+    assert_eq!(sample.sample_rate, 44100.);
+    assert_eq!(sample.data, vec![-1., 0.5, 0., -0.5]);
+}
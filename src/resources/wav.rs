@@ -0,0 +1,224 @@
+//! WAV decoding. hound handles the common formats, and the rest (64 bit floats, padded integers) is read from the chunks directly
+use std::{fs, path::Path};
+
+use hound::{SampleFormat, WavReader};
+
+use super::{chunks, ints_to_f32, Endian, Sample, SampleError};
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+pub fn load_wav(path: &Path) -> Result<Sample, SampleError> {
+    let bytes = fs::read(path)?;
+    parse_wav(&bytes)
+}
+
+fn parse_wav(bytes: &[u8]) -> Result<Sample, SampleError> {
+    let mut reader = match WavReader::new(bytes) {
+        Ok(reader) => reader,
+        // hound rejects some valid headers, like plain 64 bit float ones
+        Err(err) => return parse_raw(bytes, err),
+    };
+    let spec = reader.spec();
+    let data = match (spec.sample_format, spec.bits_per_sample) {
+        (SampleFormat::Int, bits @ (8 | 16 | 24 | 32)) => {
+            // scale by the bit depth, so every depth ends up in -1..1
+            let scale = 1. / (1_i64 << (bits - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|val| val.map(|val| val as f32 * scale))
+                .collect::<Result<Vec<f32>, hound::Error>>()
+        }
+        (SampleFormat::Float, 32) => reader.samples::<f32>().collect(),
+        _ => Err(hound::Error::Unsupported),
+    };
+    match data {
+        Ok(data) => Ok(Sample {
+            data,
+            sample_rate: spec.sample_rate as f32,
+            channels: spec.channels as usize,
+        }),
+        // e.g. samples padded to a wider container, which hound can't always read
+        Err(err) => parse_raw(bytes, err),
+    }
+}
+
+// the parts of the fmt chunk that matter for decoding
+struct WavFormat {
+    tag: u16,
+    channels: u16,
+    sample_rate: u32,
+    block_align: u16,
+    bits: u16,
+}
+
+impl WavFormat {
+    fn parse(chunk: &[u8]) -> Option<WavFormat> {
+        let read_u16 = |at: usize| {
+            chunk
+                .get(at..at + 2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        };
+        let mut tag = read_u16(0)?;
+        // extensible files keep the real format tag at the start of the subformat guid
+        if tag == WAVE_FORMAT_EXTENSIBLE {
+            tag = read_u16(24)?;
+        }
+        Some(WavFormat {
+            tag,
+            channels: read_u16(2)?,
+            sample_rate: Endian::Little.read_u32(chunk.get(4..8)?),
+            block_align: read_u16(12)?,
+            bits: read_u16(14)?,
+        })
+    }
+}
+
+// decodes the data chunk by hand. `err` is what hound said, for when the file turns out to be broken
+fn parse_raw(bytes: &[u8], err: hound::Error) -> Result<Sample, SampleError> {
+    let mut format = None;
+    let mut data = None;
+    for (id, chunk) in chunks(bytes, Endian::Little) {
+        match id {
+            b"fmt " => format = WavFormat::parse(chunk),
+            b"data" => data = Some(chunk),
+            _ => (),
+        }
+    }
+    let (format, data) = match (format, data) {
+        (Some(format), Some(data)) => (format, data),
+        _ => return Err(err.into()),
+    };
+    if format.tag != WAVE_FORMAT_PCM && format.tag != WAVE_FORMAT_IEEE_FLOAT {
+        return Err(SampleError::WavCodec(format.tag));
+    }
+    if format.channels == 0 || format.block_align == 0 || format.block_align % format.channels != 0
+    {
+        return Err(err.into());
+    }
+    // a data chunk that was cut short can end partway through a frame, which would throw off the channels
+    let block_align = format.block_align as usize;
+    let data = &data[..data.len() / block_align * block_align];
+    // the number of bytes each sample point is stored in, which can be more than its bit depth
+    let width = block_align / format.channels as usize;
+    let data = match (format.tag, width) {
+        // 8 bit wav is unsigned
+        (WAVE_FORMAT_PCM, 1..=4) => ints_to_f32(data, width, Endian::Little, width == 1),
+        (WAVE_FORMAT_IEEE_FLOAT, 4) => data
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect(),
+        (WAVE_FORMAT_IEEE_FLOAT, 8) => data
+            .chunks_exact(8)
+            .map(|b| f64::from_le_bytes(b.try_into().unwrap()) as f32)
+            .collect(),
+        (WAVE_FORMAT_PCM, _) => {
+            return Err(SampleError::WavSamples(SampleFormat::Int, format.bits))
+        }
+        _ => return Err(SampleError::WavSamples(SampleFormat::Float, format.bits)),
+    };
+    Ok(Sample {
+        data,
+        sample_rate: format.sample_rate as f32,
+        channels: format.channels as usize,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a fmt chunk. `subformat` makes it an extensible one, with the valid bits and the real format tag
+    fn fmt_chunk(tag: u16, channels: u16, bits: u16, subformat: Option<(u16, u16)>) -> Vec<u8> {
+        let block_align = channels * bits / 8;
+        let mut chunk = vec![];
+        chunk.extend_from_slice(&tag.to_le_bytes());
+        chunk.extend_from_slice(&channels.to_le_bytes());
+        chunk.extend_from_slice(&44100_u32.to_le_bytes());
+        chunk.extend_from_slice(&(44100 * block_align as u32).to_le_bytes());
+        chunk.extend_from_slice(&block_align.to_le_bytes());
+        chunk.extend_from_slice(&bits.to_le_bytes());
+        if let Some((valid_bits, tag)) = subformat {
+            chunk.extend_from_slice(&22_u16.to_le_bytes());
+            chunk.extend_from_slice(&valid_bits.to_le_bytes());
+            // channel mask
+            chunk.extend_from_slice(&0_u32.to_le_bytes());
+            chunk.extend_from_slice(&tag.to_le_bytes());
+            chunk.extend_from_slice(&[
+                0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+            ]);
+        }
+        chunk
+    }
+
+    fn wav(fmt: Vec<u8>, data: &[u8]) -> Vec<u8> {
+        super::super::build_chunks(
+            b"RIFF",
+            b"WAVE",
+            &[(b"fmt ", &fmt), (b"data", data)],
+            Endian::Little,
+        )
+    }
+
+    #[test]
+    fn decode_formats() {
+        let ints: Vec<u8> = [i32::MIN, 1 << 30]
+            .iter()
+            .flat_map(|val| val.to_le_bytes())
+            .collect();
+        let doubles: Vec<u8> = [-1_f64, 0.5]
+            .iter()
+            .flat_map(|val| val.to_le_bytes())
+            .collect();
+        let files = [
+            // 32 bit integers, through hound
+            wav(fmt_chunk(WAVE_FORMAT_PCM, 1, 32, None), &ints),
+            // 8 bit is unsigned
+            wav(fmt_chunk(WAVE_FORMAT_PCM, 1, 8, None), &[0, 192]),
+            // 24 valid bits in a 32 bit container
+            wav(
+                fmt_chunk(WAVE_FORMAT_EXTENSIBLE, 1, 32, Some((24, WAVE_FORMAT_PCM))),
+                &ints,
+            ),
+            wav(fmt_chunk(WAVE_FORMAT_IEEE_FLOAT, 1, 64, None), &doubles),
+            wav(
+                fmt_chunk(
+                    WAVE_FORMAT_EXTENSIBLE,
+                    1,
+                    64,
+                    Some((64, WAVE_FORMAT_IEEE_FLOAT)),
+                ),
+                &doubles,
+            ),
+        ];
+        for file in files {
+            let sample = parse_wav(&file).unwrap();
+            assert_eq!(sample.sample_rate, 44100.);
+            assert_eq!(sample.data, vec![-1., 0.5]);
+        }
+    }
+
+    #[test]
+    fn truncated_and_unsupported() {
+        // a stereo 16 bit file cut off in the middle of its second frame. hound won't read it, so it's trimmed by hand
+        let file = wav(
+            fmt_chunk(WAVE_FORMAT_PCM, 2, 16, None),
+            &[0, 0x80, 0, 0x40, 0, 0],
+        );
+        assert!(WavReader::new(&file[..]).is_err());
+        let sample = parse_wav(&file).unwrap();
+        assert_eq!(sample.data, vec![-1., 0.5]);
+
+        let adpcm = wav(fmt_chunk(0x0002, 1, 4, None), &[0; 4]);
+        assert_eq!(
+            parse_wav(&adpcm).err().unwrap().to_string(),
+            "wav: compressed format 0x0002 isn't supported"
+        );
+        let half_floats = wav(fmt_chunk(WAVE_FORMAT_IEEE_FLOAT, 1, 16, None), &[0; 4]);
+        assert_eq!(
+            parse_wav(&half_floats).err().unwrap().to_string(),
+            "wav: 16 bit float samples aren't supported"
+        );
+    }
+}
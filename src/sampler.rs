@@ -1,91 +1,185 @@
 // For now the sampler will just be a modified wavetable lol
 // TODO: More mips
-// FIXME: Crash when playing note and changing sample. Dropping mutex too early or running the check for new sample too late or removing sample data too early?
 // FIXME: finding mip should take base_pitch into account, lest we get aliasing
 
-const N_VOICES: usize = 8;
+use crate::parameters::LoopMode;
 use crate::parameters::OnOff;
 use crate::parameters::SamplerParams;
+use crate::parameters::TriggerMode;
+use crate::parameters::VelCurve;
 use crate::utils::AtomicOps;
 use crate::Voice;
 
 mod fir;
+mod keymap;
+
+pub use keymap::{to_midi_velocity, Keymap, RoundRobinState, Zone};
+
+// the shortest part of the sample that can be played, as a fraction of the sample's length
+const MIN_PLAY_LENGTH: f32 = 0.01;
+// the fewest frames SampleInterp can build its mips from. Each of the 9 mips halves the length, and the last one needs 3 frames
+const MIN_FRAMES: usize = 1024;
+// the mips only line up when the length halves evenly all the way down
+const FRAME_ALIGN: usize = 256;
+
+// how many frames SampleInterp pads a sample of `frames` frames to, with silence at the end.
+// Positions in the sample have to be worked out as fractions of this
+#[inline]
+pub fn padded_frames(frames: usize) -> usize {
+    frames.max(MIN_FRAMES).div_ceil(FRAME_ALIGN) * FRAME_ALIGN
+}
 
 #[inline]
 pub fn calc_relative_pitch(note: f32, root: f32) -> f32 {
     2f32.powf((note - root) / 12.)
 }
+// velocity is in [0, 1]. amount blends between ignoring velocity and following the curve fully
+pub fn calc_velocity_gain(velocity: f32, amount: f32, curve: VelCurve) -> f32 {
+    let curved = match curve {
+        VelCurve::Linear => velocity,
+        VelCurve::Exponential => velocity * velocity,
+        VelCurve::Logarithmic => (1. + 9. * velocity).log10(),
+        VelCurve::Fixed => 1.,
+    };
+    1. - amount + amount * curved
+}
+// constant power pan law, scaled so center pan leaves the level unchanged. pan is in [-1, 1]
+#[inline]
+pub fn calc_pan_gains(pan: f32) -> [f32; 2] {
+    let angle = (pan + 1.) * std::f32::consts::FRAC_PI_4;
+    [
+        angle.cos() * std::f32::consts::SQRT_2,
+        angle.sin() * std::f32::consts::SQRT_2,
+    ]
+}
+// the start and end of the part of the sample that gets played, as fractions of the sample's length.
+// The zone's own positions take precedence over the params. The end is always after the start
+#[inline]
+pub fn calc_play_range(values: &SmoothedValues, zone: &Zone) -> (f32, f32) {
+    // a zone can start anywhere up to the very end of its sample, which would leave no room for the end
+    let start = zone.start.unwrap_or(values.pos).min(1. - MIN_PLAY_LENGTH);
+    let end = zone
+        .end
+        .unwrap_or(values.end)
+        .clamp(start + MIN_PLAY_LENGTH, 1.);
+    (start, end)
+}
+// the start and end of the loop, as fractions of the sample's length. The loop never goes past the end of the playback
+#[inline]
+pub fn calc_loop_range(params: &SamplerParams, zone: &Zone, end: f32) -> (f32, f32) {
+    let loop_end = zone.loop_end.unwrap_or(params.loop_end.value).min(end);
+    let loop_start = zone
+        .loop_start
+        .unwrap_or(params.loop_start.value)
+        .min(loop_end - MIN_PLAY_LENGTH)
+        .max(0.);
+    (loop_start, loop_end)
+}
+
+// fades from a to b. b is None if it's outside of the data, and then a is used as is
+#[inline]
+fn crossfade(a: [f32; 2], b: Option<[f32; 2]>, fade_in: f32) -> [f32; 2] {
+    match b {
+        Some(b) => [
+            a[0] + (b[0] - a[0]) * fade_in,
+            a[1] + (b[1] - a[1]) * fade_in,
+        ],
+        None => a,
+    }
+}
+
+/// The current values of the smoothed params. They're pulled from the smoothers once per output sample,
+/// so automating them doesn't cause zipper noise
+#[derive(Clone, Copy, Default)]
+pub struct SmoothedValues {
+    pub volume: f32,
+    pub pan: f32,
+    pub pos: f32,
+    pub end: f32,
+    // coarse and fine tune added together, in semitones
+    pub tune: f32,
+    pub cutoff: f32,
+    pub resonance: f32,
+}
+impl SmoothedValues {
+    pub fn next(params: &SamplerParams) -> SmoothedValues {
+        SmoothedValues {
+            volume: params.volume.smoothed.next(),
+            pan: params.pan.smoothed.next(),
+            pos: params.pos.smoothed.next(),
+            end: params.end.smoothed.next(),
+            tune: params.coarse_tune.smoothed.next() + params.fine_tune.smoothed.next(),
+            cutoff: params.cutoff.smoothed.next(),
+            resonance: params.resonance.smoothed.next(),
+        }
+    }
+}
+
 pub struct Sampler<'a> {
-    data: SampleInterp<'a>,
+    // double buffered sample data, so voices can finish playing the previous sample while a new one is in use
+    slots: [Box<Keymap<'a>>; 2],
+    // the slot new notes play from
+    active_slot: usize,
+    // new data waiting for the inactive slot to be free
+    next_data: Option<Box<Keymap<'a>>>,
     // phase: f32,
-    base_pitch: f32,
     standard_pitch: f32,
     pub sample_rate: f32,
-    ratios: [f32; N_VOICES],
-    // phase_incs: [f32; N_VOICES],
-    current_mip: [usize; N_VOICES],
-    // for one-shotting the sample
-    pub is_done: [bool; N_VOICES],
+    // the pitch wheel's position, from -1 to 1
+    pub pitchbend: f32,
+    pub smoothed: SmoothedValues,
 }
 
 impl<'a> Sampler<'a> {
-    pub fn new() -> Sampler<'a> {
+    pub fn new(data: Box<Keymap<'a>>) -> Sampler<'a> {
         Sampler {
-            data: SampleInterp::default(),
+            slots: [data, Box::new(Keymap::default())],
+            active_slot: 0,
+            next_data: None,
             standard_pitch: 21.533203125 * 2.,
-            base_pitch: 1.,
             // phase: 0.,
             sample_rate: 44100.,
-            // phase_incs: [0.; N_VOICES],
-            ratios: [0.; N_VOICES],
-            current_mip: [0; N_VOICES],
-            is_done: [true; N_VOICES],
+            pitchbend: 0.,
+            smoothed: SmoothedValues::default(),
         }
     }
     // TODO: Divide total_pitch by 2.powi(current_mip) most likely, if get_sample doesn't use pitch anywhere else
-    pub fn pitch_params_changed(&mut self, params: &SamplerParams, voices: &Vec<Voice>) {
-        for i in 0..N_VOICES {
-            let pitch = if params.keytrack.value() == OnOff::On {
-                calc_relative_pitch(
-                    params.coarse_tune.value + params.fine_tune.value + voices[i].current_notepitch, // + voice.current_note as f64,
-                    // TODO: sample should be able to pitchbend
-                    // + self.pitchbend * self.params.pitchbend_amt.get() as f32,
-                    params.root.value as f32,
-                )
-            } else {
-                calc_relative_pitch(
-                    params.coarse_tune.value + params.fine_tune.value + params.root.value as f32, // + voice.current_note as f64,
-                    params.root.value as f32,
-                )
-            };
-            let mut pitch_mut = pitch * self.base_pitch;
-            let mut current_mip = 0;
-            // let phase_wrapped = (self.phase_wraparound(*phase + phase_mod));,
-            // TODO: Hmm, base_pitch should be used to select mip map maybe?
-            // let ratio = (pitch) / self.standard_pitch;
-            while pitch_mut > 2. {
-                pitch_mut /= 2.;
-                current_mip += 1;
-            }
-            self.ratios[i] = pitch_mut;
-            self.current_mip[i] = current_mip;
+    pub fn pitch_params_changed(&mut self, params: &SamplerParams, voices: &mut [Voice]) {
+        for voice in voices.iter_mut() {
+            self.voice_pitch_changed(params, voice);
+        }
+    }
+    /// The current pitch bend in semitones
+    pub fn bend(&self, params: &SamplerParams) -> f32 {
+        if self.pitchbend > 0. {
+            self.pitchbend * params.bend_up.value as f32
+        } else {
+            self.pitchbend * params.bend_down.value as f32
         }
     }
-    pub fn voice_pitch_changed(&mut self, params: &SamplerParams, voices: &Vec<Voice>, idx: usize) {
+    pub fn voice_pitch_changed(&mut self, params: &SamplerParams, voice: &mut Voice) {
+        let zone = match self.zone(voice) {
+            Some(zone) => zone,
+            None => return,
+        };
+        let root = match zone.root {
+            Some(root) => root as f32,
+            None => params.root.value as f32,
+        };
+        let tune = self.smoothed.tune + zone.tune;
+        let bend = self.bend(params);
         let pitch = if params.keytrack.value() == OnOff::On {
             calc_relative_pitch(
-                params.coarse_tune.value + params.fine_tune.value + voices[idx].current_notepitch, // + voice.current_note as f64,
-                // TODO: sample should be able to pitchbend
-                // + self.pitchbend * self.params.pitchbend_amt.get() as f32,
-                params.root.value as f32,
+                tune + voice.current_notepitch + bend, // + voice.current_note as f64,
+                root,
             )
         } else {
             calc_relative_pitch(
-                params.coarse_tune.value + params.fine_tune.value + params.root.value as f32, // + voice.current_note as f64,
-                params.root.value as f32,
+                tune + root + bend, // + voice.current_note as f64,
+                root,
             )
         };
-        let mut pitch_mut = pitch * self.base_pitch;
+        let mut pitch_mut = pitch * self.base_pitch(zone);
         // println!("pitch: {}", pitch_mut);
         let mut current_mip = 0;
         // let phase_wrapped = (self.phase_wraparound(*phase + phase_mod));,
@@ -95,85 +189,133 @@ impl<'a> Sampler<'a> {
             pitch_mut /= 2.;
             current_mip += 1;
         }
-        self.ratios[idx] = pitch_mut;
-        self.current_mip[idx] = current_mip;
+        voice.ratio = pitch_mut;
+        voice.current_mip = current_mip;
     }
-    pub fn process(&mut self, voice_n: usize, phase: &mut f32, params: &SamplerParams) -> [f32; 2] {
-        if params.is_on.value() == OnOff::Off || self.is_done[voice_n] {
+    pub fn process(&self, voice: &mut Voice, params: &SamplerParams) -> [f32; 2] {
+        if params.is_on.value() == OnOff::Off || voice.is_done {
             [0.; 2]
         } else {
-            self.get_sample(voice_n, phase, params)
+            let output = self.get_sample(voice, params);
+            let declick = voice.next_declick();
+            [output[0] * declick, output[1] * declick]
         }
     }
 
-    pub fn get_sample(
-        &mut self,
-        voice_n: usize,
-        phase: &mut f32,
-        params: &SamplerParams,
-    ) -> [f32; 2] {
-        let current_mip = self.current_mip[voice_n];
-        let ratio = self.ratios[voice_n];
-        // let pitch = self.total_pitches[voice_n];
-        let osc = &self.data;
-        // let params = &self.params;
-        // used to do the phase modulation
-        let max_phase = (osc.len) as f32;
-        // mip_offset moves us to the right mip-map
-        let mip_offset = mip_offset(current_mip, osc.len);
-        // ratio also needs to take into account that higher mips have half as many samples:
-        // let downsample_ratio = 2usize.pow(current_mip as u32);
-        // somewhat faster than the line above
-        let downsample_ratio = 1 << current_mip;
+    pub fn get_sample(&self, voice: &mut Voice, params: &SamplerParams) -> [f32; 2] {
+        let zone = match self.zone(voice) {
+            Some(zone) => zone,
+            None => {
+                voice.is_done = true;
+                return [0.; 2];
+            }
+        };
+        let osc = &zone.data;
+        let phase = voice.sampler_phase;
+        let current_mip = voice.current_mip;
+        // the phase is only valid for the data it was computed against, so never trust it blindly
+        let output = match osc.read(phase, current_mip) {
+            Some(output) => output,
+            None => {
+                voice.is_done = true;
+                return [0.; 2];
+            }
+        };
+        // a looping one-shot would never end, and neither would a voice that was started by letting go of a key.
+        // A loop that only runs while the key is held lets the sample play out once the key is let go
+        let loop_mode = if params.trigger_mode.value() == TriggerMode::OneShot
+            || zone.one_shot
+            || zone.on_release
+            || (zone.loop_while_held && !voice.is_on && !voice.sustained)
+        {
+            LoopMode::Off
+        } else {
+            zone.loop_mode.unwrap_or_else(|| params.loop_mode.value())
+        };
+        let forward = voice.direction > 0.;
+        let (start, end) = calc_play_range(&self.smoothed, zone);
+        let (loop_start, loop_end) = calc_loop_range(params, zone, end);
+        let loop_len = loop_end - loop_start;
+        let xfade = params.loop_xfade.value / 1000. * osc.sample_rate / osc.len as f32;
+        // when the loop jumps from one end to the other, the last bit before the jump is faded into what comes after it,
+        // so the jump doesn't click. That takes as much data outside the loop as the crossfade is long
+        let output = match (loop_mode, forward) {
+            (LoopMode::Forward, true) => {
+                let xfade = xfade.min(loop_start).min(loop_len);
+                if xfade > 0. && phase >= loop_end - xfade && phase < loop_end {
+                    let fade_in = (phase - (loop_end - xfade)) / xfade;
+                    crossfade(output, osc.read(phase - loop_len, current_mip), fade_in)
+                } else {
+                    output
+                }
+            }
+            (LoopMode::Forward | LoopMode::Reverse, false) => {
+                let xfade = xfade.min(1. - loop_end).min(loop_len);
+                if xfade > 0. && phase <= loop_start + xfade && phase > loop_start {
+                    let fade_in = (loop_start + xfade - phase) / xfade;
+                    crossfade(output, osc.read(phase + loop_len, current_mip), fade_in)
+                } else {
+                    output
+                }
+            }
+            _ => output,
+        };
+        let vol = self.smoothed.volume * zone.volume * voice.velocity_gain;
+        let [pan_l, pan_r] = calc_pan_gains((self.smoothed.pan + zone.pan).clamp(-1., 1.));
 
         // ratio, or 1/sample_pitch give us how many samples we need to iterate forward
         // should never be more than 2 because of the mip mapping
-        // downsampling phase to the right mip
-        let phase_mipped = ((*phase + params.pos.value) * max_phase) / downsample_ratio as f32;
-        // index gets us to the right polynomium, z lets us find the right sample value at the polynomium.
-        let index = phase_mipped.floor() as usize + mip_offset;
-
-        // x is kind of like time in between the samples and in the range [0,1], and therefore the fraction part of the phase
-        // z is x - 0.5, which is basically a "coefficient offset" on the polynomial interpolation matrix.
-        // this offset saves a few multiplications
-        let z = phase_mipped - phase_mipped.floor() - 0.5;
-        let vol = params.volume.value;
-        let output = if params.sample_mono.get() {
-            let output = ((osc.c3_l[index] * z + osc.c2_l[index]) * z + osc.c1_l[index]) * z
-                + osc.c0_l[index];
-            [output * vol, output * vol]
-        } else {
-            [
-                (((osc.c3_l[index] * z + osc.c2_l[index]) * z + osc.c1_l[index]) * z
-                    + osc.c0_l[index])
-                    * vol,
-                (((osc.c3_r[index] * z + osc.c2_r[index]) * z + osc.c1_r[index]) * z
-                    + osc.c0_r[index])
-                    * vol,
-            ]
-        };
+        // ratio also needs to take into account that higher mips have half as many samples:
         // TODO: The downsample_ratio stuff could prolly be precalc'd
-        *phase += ratio * downsample_ratio as f32 / max_phase;
-        // if the voice's phase moves outside of the correct wave, loop back around to the start
-        while *phase + params.pos.value > 1. {
-            *phase -= 1. - params.pos.value;
-            if params.is_looping.value() == OnOff::Off {
-                self.is_done[voice_n] = true;
+        let downsample_ratio = 1 << current_mip;
+        let new_phase =
+            phase + voice.direction * voice.ratio * downsample_ratio as f32 / osc.len as f32;
+        // only voices that reach the loop's edges from inside the loop take part in it, so a start position after the loop plays through
+        let crossed_end = forward && phase < loop_end && new_phase >= loop_end;
+        let crossed_start = !forward && phase > loop_start && new_phase <= loop_start;
+        voice.sampler_phase = match loop_mode {
+            LoopMode::Off => new_phase,
+            LoopMode::Forward if crossed_end => new_phase - loop_len,
+            LoopMode::Forward | LoopMode::Reverse if crossed_start => new_phase + loop_len,
+            // bounce off the loop's edges
+            LoopMode::PingPong | LoopMode::Reverse if crossed_end => {
+                voice.direction = -1.;
+                2. * loop_end - new_phase
             }
+            LoopMode::PingPong if crossed_start => {
+                voice.direction = 1.;
+                2. * loop_start - new_phase
+            }
+            _ => new_phase,
+        };
+        let lowest_phase = if loop_mode == LoopMode::Off {
+            start
+        } else {
+            start.min(loop_start)
+        };
+        // only a voice playing backwards runs out at the start. Pos can be moved past a forward voice without cutting it off.
+        // End can be lowered below a playing voice too, which fades it out rather than cutting it.
+        // Past the end of the sample there's nothing left to fade out with, so the voice is done
+        if voice.sampler_phase >= 1. {
+            voice.is_done = true;
+        } else if voice.sampler_phase >= end
+            || (voice.direction < 0. && voice.sampler_phase < lowest_phase)
+        {
+            voice.out_of_range = true;
         }
-        output
+        [output[0] * vol * pan_l, output[1] * vol * pan_r]
     }
     // For offline processing like BillyDM wanted, since it's probably a waste of time to precompute coefficients in that case
-    pub fn _get_sample_no_precompute(
-        &mut self,
-        voice_n: usize,
-        phase: &mut f32,
-        params: &SamplerParams,
-    ) -> [f32; 2] {
-        let current_mip = self.current_mip[voice_n];
-        let ratio = self.ratios[voice_n];
+    pub fn _get_sample_no_precompute(&self, voice: &mut Voice, params: &SamplerParams) -> [f32; 2] {
+        let zone = match self.zone(voice) {
+            Some(zone) => zone,
+            None => return [0.; 2],
+        };
+        let osc = &zone.data;
+        let current_mip = voice.current_mip;
+        let ratio = voice.ratio;
+        let phase = &mut voice.sampler_phase;
         // let pitch = self.total_pitches[voice_n];
-        let osc = &self.data;
         // let params = &self.params;
         // used to do the phase modulation
         let max_phase = (osc.len) as f32;
@@ -188,17 +330,16 @@ impl<'a> Sampler<'a> {
         // downsampling phase to the right mip
         let phase_mipped = (*phase * max_phase) / downsample_ratio as f32;
         // index gets us to the right polynomium, z lets us find the right sample value at the polynomium.
-        let index = phase_mipped.floor() as usize
-            + (params.pos.value * osc.len as f32) as usize / downsample_ratio;
+        let index = phase_mipped.floor() as usize;
 
         // x is kind of like time in between the samples and in the range [0,1], and therefore the fraction part of the phase
         // z is x - 0.5, which is basically a "coefficient offset" on the polynomial interpolation matrix.
         // this offset saves a few multiplications
         let z = phase_mipped - phase_mipped.floor() - 0.5;
-        let vol = params.volume.value;
-        let output = if params.sample_mono.get() {
+        let vol = self.smoothed.volume;
+        let output = if osc.mono {
             // TODO: should use mips
-            let y = &self.data.mips_l[current_mip];
+            let y = &osc.mips_l[current_mip];
             // TODO: "Bounds" checking. Gets a bit complicated with mips
             let even1;
             let odd1;
@@ -238,66 +379,88 @@ impl<'a> Sampler<'a> {
         };
         // TODO: The downsample_ratio stuff could prolly be precalc'd
         *phase += ratio * downsample_ratio as f32 / max_phase;
-        // if the voice's phase moves outside of the correct wave, loop back around to the start
-        while *phase + params.pos.value > 1. {
-            *phase -= 1. - params.pos.value;
+        // if the voice's phase moves past the end, loop back around to the start
+        let (start, end) = calc_play_range(&self.smoothed, zone);
+        while *phase >= end {
+            *phase -= end - start;
         }
         output
     }
 
-    // sets the sampler up with the new sample
-    pub fn source_changed(&mut self, params: &SamplerParams) {
-        // println!("source changed!");
-        // new table data
-        let source = params.source.read().unwrap();
-        // println!("source len {}", source.len());
-        // move to oscillator
-        // self.oscs[osc_n].source_y = source.to_vec();
-        if params.sample_mono.get() {
-            self.data.source_l = source.to_vec();
-            if self.data.source_l.len() % 2 != 0 {
-                self.data.source_l.push(0.);
+    // queues up new preprocessed sample data. Returns data that got replaced before it was ever used, so the caller can get rid of it off the audio thread
+    pub fn set_data(&mut self, data: Box<Keymap<'a>>) -> Option<Box<Keymap<'a>>> {
+        self.next_data.replace(data)
+    }
+    // moves queued data into the inactive slot once no voice is playing from it anymore, and makes it the active one.
+    // Returns the data that was in the slot before
+    pub fn swap_data(&mut self, voices: &mut [Voice]) -> Option<Box<Keymap<'a>>> {
+        self.next_data.as_ref()?;
+        let free_slot = 1 - self.active_slot;
+        let mut slot_in_use = false;
+        for voice in voices.iter_mut() {
+            if voice.is_active() && !voice.is_done && voice.slot == free_slot {
+                // voices still playing the sample from before the previous one are faded out,
+                // voices playing the previous sample get to finish on the active slot
+                voice.declick(self.sample_rate);
+                slot_in_use = true;
             }
-            // drop mutex as soon as possible
-            drop(source);
-            self.set_base_pitch(params);
-            self.standard_pitch = self.sample_rate * 2. / self.data.len as f32;
-            // run the WaveTable's setup function to complete preprocessing
-            self.data.setup_mono(true);
         }
-        // TODO: Test if this is right
-        else {
-            // for (i, val) in source.iter().enumerate().map(|x|, (x.0,x)).step_by(2) {
-            self.data.source_l.clear();
-            self.data.source_r.clear();
-            for i in (0..source.len()).step_by(2) {
-                self.data.source_l.push(source[i]);
-                self.data.source_r.push(source[i + 1]);
-            }
-            // attempt at fixing things by padding
-            if self.data.source_l.len() % 2 != 0 {
-                self.data.source_l.push(0.);
-                self.data.source_r.push(0.);
-            }
-            // drop mutex as soon as possible
-            drop(source);
-            self.set_base_pitch(params);
-            // run the WaveTable's setup function to complete preprocessing
-            self.standard_pitch = self.sample_rate * 2. / self.data.len as f32;
-            self.data.setup_stereo(true);
-            // assert!(self.data.source_l.len() == self.data.source_r.len(), "somehow the 2 channels are different lengths");
+        if slot_in_use {
+            return None;
         }
-        // println!("sample len: {}", source.len());
+        let data = self.next_data.take()?;
+        let old_data = std::mem::replace(&mut self.slots[free_slot], data);
+        self.active_slot = free_slot;
+        self.update_standard_pitch();
+        Some(old_data)
+    }
+    /// Puts the voice at the start of the play range, or at its end when playing in reverse
+    pub fn reset_phase(&self, params: &SamplerParams, voice: &mut Voice) {
+        let zone = match self.zone(voice) {
+            Some(zone) => zone,
+            None => return,
+        };
+        let (start, end) = calc_play_range(&self.smoothed, zone);
+        if params.reverse.value() == OnOff::On {
+            // one sample before the end, since the last polynomial reaches up to the end
+            let len = zone.data.len.max(1) as f32;
+            voice.sampler_phase = (end - 1. / len).max(start);
+            voice.direction = -1.;
+        } else {
+            voice.sampler_phase = start;
+            voice.direction = 1.;
+        }
+    }
+    // the slot new voices should play from
+    pub fn active_slot(&self) -> usize {
+        self.active_slot
+    }
+    // voices playing one-shot zones ignore note offs
+    pub fn is_one_shot(&self, voice: &Voice) -> bool {
+        self.zone(voice).map_or(false, |zone| zone.one_shot)
     }
-    pub fn set_base_pitch(&mut self, params: &SamplerParams) {
-        let rate_of_sample = params.sample_sample_rate.get();
+    // the keymap new voices should pick their zones from
+    pub fn active_keymap(&self) -> &Keymap<'a> {
+        &self.slots[self.active_slot]
+    }
+    // the zone the voice is playing. None if the voice's zone isn't in its slot's keymap
+    fn zone(&self, voice: &Voice) -> Option<&Zone<'a>> {
+        self.slots[voice.slot].zones.get(voice.zone)
+    }
+    fn base_pitch(&self, zone: &Zone) -> f32 {
+        let rate_of_sample = zone.data.sample_rate;
         // high sample rate has too many samples - iterate through it faster
         // times 2 because of internal oversampling
-        self.base_pitch = rate_of_sample / (self.sample_rate * 2.);
+        rate_of_sample / (self.sample_rate * 2.)
     }
     pub fn set_standard_pitch(&mut self, rate: f32) {
         self.sample_rate = rate;
-        self.standard_pitch = self.sample_rate * 2. / self.data.len as f32;
+        self.update_standard_pitch();
+    }
+    fn update_standard_pitch(&mut self) {
+        if let Some(zone) = self.active_keymap().zones.first() {
+            self.standard_pitch = self.sample_rate * 2. / zone.data.len as f32;
+        }
     }
 }
 
@@ -306,6 +469,10 @@ pub struct SampleInterp<'a> {
     pub source_r: Vec<f32>,
     /// the number of waveforms in the current wavetable
     pub(crate) len: usize,
+    /// whether only the left channel holds data
+    pub mono: bool,
+    /// sample rate the sample was recorded at
+    pub sample_rate: f32,
 
     mips_l: Vec<Vec<f32>>,
     mips_r: Vec<Vec<f32>>,
@@ -324,6 +491,78 @@ pub struct SampleInterp<'a> {
 }
 
 impl<'a> SampleInterp<'a> {
+    // builds the mips and coefficients for a sample. Allocates a lot, so don't call this from the audio thread
+    pub fn new(source: &[f32], mono: bool, sample_rate: f32) -> SampleInterp<'a> {
+        let mut data = SampleInterp {
+            mono,
+            sample_rate,
+            ..SampleInterp::default()
+        };
+        if mono {
+            data.source_l = source.to_vec();
+            data.source_l.resize(padded_frames(source.len()), 0.);
+            // run the WaveTable's setup function to complete preprocessing
+            data.setup_mono(true);
+        }
+        // TODO: Test if this is right
+        else {
+            for i in (0..source.len()).step_by(2) {
+                data.source_l.push(source[i]);
+                data.source_r.push(source[i + 1]);
+            }
+            let frames = padded_frames(data.source_l.len());
+            data.source_l.resize(frames, 0.);
+            data.source_r.resize(frames, 0.);
+            data.setup_stereo(true);
+            // assert!(data.source_l.len() == data.source_r.len(), "somehow the 2 channels are different lengths");
+        }
+        data
+    }
+    pub fn from_params(params: &SamplerParams) -> SampleInterp<'a> {
+        let source = params.source.read().unwrap();
+        SampleInterp::new(
+            &source,
+            params.sample_mono.get(),
+            params.sample_sample_rate.get(),
+        )
+    }
+    // reads the interpolated value at phase, a fraction of the sample's length, from the given mip.
+    // Returns None if phase is outside of the data
+    pub fn read(&self, phase: f32, mip: usize) -> Option<[f32; 2]> {
+        if phase < 0. {
+            return None;
+        }
+        // higher mips have half as many samples:
+        // let downsample_ratio = 2usize.pow(current_mip as u32);
+        // somewhat faster than the line above
+        let downsample_ratio = 1 << mip;
+        // downsampling phase to the right mip
+        let phase_mipped = (phase * self.len as f32) / downsample_ratio as f32;
+        // index gets us to the right polynomium, z lets us find the right sample value at the polynomium.
+        // mip_offset moves us to the right mip-map
+        let mip_start = mip_offset(mip, self.len);
+        let index = phase_mipped.floor() as usize + mip_start;
+        // the next mip starts right after this one, so reading past the end would play a downsampled copy of the start
+        if index >= mip_start + (self.len >> mip)
+            || index >= self.c0_l.len()
+            || (!self.mono && index >= self.c0_r.len())
+        {
+            return None;
+        }
+        // x is kind of like time in between the samples and in the range [0,1], and therefore the fraction part of the phase
+        // z is x - 0.5, which is basically a "coefficient offset" on the polynomial interpolation matrix.
+        // this offset saves a few multiplications
+        let z = phase_mipped - phase_mipped.floor() - 0.5;
+        let left = ((self.c3_l[index] * z + self.c2_l[index]) * z + self.c1_l[index]) * z
+            + self.c0_l[index];
+        if self.mono {
+            Some([left, left])
+        } else {
+            let right = ((self.c3_r[index] * z + self.c2_r[index]) * z + self.c1_r[index]) * z
+                + self.c0_r[index];
+            Some([left, right])
+        }
+    }
     // prepares coeffs after loading new table
     pub fn setup_mono(&mut self, precompute: bool) {
         self.len = self.source_l.len();
@@ -497,6 +736,8 @@ impl<'a> Default for SampleInterp<'a> {
             mips_r: Vec::with_capacity(2048 * 8),
             mip_levels: 9,
             len: 0,
+            mono: true,
+            sample_rate: 44100.,
             c0_l: Vec::with_capacity(2048 * 8 * 2),
             c1_l: Vec::with_capacity(2048 * 8 * 2),
             c2_l: Vec::with_capacity(2048 * 8 * 2),
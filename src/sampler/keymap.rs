@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::{padded_frames, SampleInterp};
+use crate::parameters::{LoopMode, RoundRobin, SamplerParams};
+use crate::resources::{load_audio, Sample};
+use crate::sf2::{Sf2Error, Sf2LoopMode, Sf2Region, SoundFont};
+use crate::sfz::{self, Region, SfzError, SfzLoopMode, SfzTrigger};
+
+// velocities come in as [0, 1], but zones are mapped with midi velocities
+#[inline]
+pub fn to_midi_velocity(velocity: f32) -> u8 {
+    (velocity * 127.).round().clamp(0., 127.) as u8
+}
+
+/// A sample together with the keys and velocities it plays on, and how it's tuned
+pub struct Zone<'a> {
+    pub data: SampleInterp<'a>,
+    // inclusive ranges of midi notes and velocities (0-127)
+    pub lokey: u8,
+    pub hikey: u8,
+    pub lovel: u8,
+    pub hivel: u8,
+    // the note the sample plays at its original pitch. None uses the root param
+    pub root: Option<u8>,
+    // in semitones, on top of the tuning params
+    pub tune: f32,
+    // gain multiplied with the volume param
+    pub volume: f32,
+    // added to the pan param
+    pub pan: f32,
+    // choke group of the zone. 0 is no group
+    pub group: u32,
+    // playing a zone in this group cuts the zone off. Only used when the zone has a group
+    pub off_by: u32,
+    // zones taking turns on a key each play on every round_robin-th press of it, the one at round_robin_position
+    // counting from 0. Worked out when the keymap is loaded, from sfz's seq_length and seq_position. 0 always plays
+    pub round_robin: u8,
+    pub round_robin_position: u8,
+    // these override the params of the same name when set. Positions are fractions of the sample's length
+    pub start: Option<f32>,
+    pub end: Option<f32>,
+    pub loop_mode: Option<LoopMode>,
+    pub loop_start: Option<f32>,
+    pub loop_end: Option<f32>,
+    // the loop only runs while the key is held, then the sample plays on to the end
+    pub loop_while_held: bool,
+    // note offs don't release the zone
+    pub one_shot: bool,
+    // the zone plays when the key is let go instead of when it's pressed
+    pub on_release: bool,
+}
+
+impl<'a> Zone<'a> {
+    /// A zone covering every note and velocity, played at the root param's pitch
+    pub fn new(data: SampleInterp<'a>) -> Zone<'a> {
+        Zone {
+            data,
+            lokey: 0,
+            hikey: 127,
+            lovel: 0,
+            hivel: 127,
+            root: None,
+            tune: 0.,
+            volume: 1.,
+            pan: 0.,
+            group: 0,
+            off_by: 0,
+            round_robin: 0,
+            round_robin_position: 0,
+            start: None,
+            end: None,
+            loop_mode: None,
+            loop_start: None,
+            loop_end: None,
+            loop_while_held: false,
+            one_shot: false,
+            on_release: false,
+        }
+    }
+    // maps the region's opcodes onto a zone playing its decoded sample
+    fn from_region(region: &Region, sample: &Sample) -> Result<Zone<'a>, KeymapError> {
+        if sample.sample_rate == 0. || !(1..=2).contains(&sample.channels) {
+            return Err(KeymapError::Sample(
+                region.sample.clone(),
+                String::from("unsupported sample rate or number of channels"),
+            ));
+        }
+        let frames = padded_frames(sample.data.len() / sample.channels) as f32;
+        let position = |frame: u32| (frame as f32 / frames).clamp(0., 1.);
+        let mut zone = Zone::new(SampleInterp::new(
+            &sample.data,
+            sample.channels == 1,
+            sample.sample_rate,
+        ));
+        zone.lokey = region.lokey;
+        zone.hikey = region.hikey;
+        zone.lovel = region.lovel;
+        zone.hivel = region.hivel;
+        zone.root = Some(region.pitch_keycenter);
+        zone.tune = region.tune as f32 / 100.;
+        zone.volume = 10f32.powf(region.volume / 20.);
+        zone.pan = region.pan / 100.;
+        zone.group = region.group;
+        zone.off_by = region.off_by;
+        if region.seq_length > 1 {
+            zone.round_robin = region.seq_length.min(u8::MAX as u32) as u8;
+            zone.round_robin_position =
+                region.seq_position.saturating_sub(1).min(u8::MAX as u32) as u8;
+        }
+        zone.start = region.offset.map(position);
+        zone.end = region.end.map(position);
+        zone.loop_start = region.loop_start.map(position);
+        zone.loop_end = region.loop_end.map(position);
+        match region.loop_mode {
+            Some(SfzLoopMode::NoLoop) => zone.loop_mode = Some(LoopMode::Off),
+            Some(SfzLoopMode::OneShot) => {
+                zone.loop_mode = Some(LoopMode::Off);
+                zone.one_shot = true;
+            }
+            Some(SfzLoopMode::LoopContinuous) => zone.loop_mode = Some(LoopMode::Forward),
+            Some(SfzLoopMode::LoopSustain) => {
+                zone.loop_mode = Some(LoopMode::Forward);
+                zone.loop_while_held = true;
+            }
+            None => (),
+        }
+        zone.on_release = region.trigger == SfzTrigger::Release;
+        Ok(zone)
+    }
+    // maps the sound font region onto a zone, with its part of the sound font's sample data
+    fn from_sf2_region(region: &Sf2Region, sample_data: &[i16]) -> Result<Zone<'a>, KeymapError> {
+        if region.sample_rate == 0 {
+            return Err(KeymapError::Sf2(Sf2Error::Format(
+                "a sample has a sample rate of 0",
+            )));
+        }
+        let source = sample_data[region.start as usize..region.end as usize]
+            .iter()
+            .map(|value| *value as f32 / i16::MAX as f32)
+            .collect::<Vec<f32>>();
+        // sound fonts are full of single cycle loops, which get padded to be long enough for the mips
+        let frames = padded_frames(source.len()) as f32;
+        let position = |frame: u32| ((frame as f32 - region.start as f32) / frames).clamp(0., 1.);
+        let mut zone = Zone::new(SampleInterp::new(&source, true, region.sample_rate as f32));
+        zone.lokey = region.lokey;
+        zone.hikey = region.hikey;
+        zone.lovel = region.lovel;
+        zone.hivel = region.hivel;
+        zone.root = Some(region.root);
+        zone.tune = region.tune as f32 / 100.;
+        zone.volume = 10f32.powf(-region.attenuation / 20.);
+        zone.pan = region.pan;
+        // notes in an exclusive class cut each other off
+        zone.group = region.exclusive_class as u32;
+        zone.off_by = region.exclusive_class as u32;
+        zone.loop_start = Some(position(region.loop_start));
+        zone.loop_end = Some(position(region.loop_end));
+        zone.loop_mode = Some(match region.loop_mode {
+            Sf2LoopMode::NoLoop => LoopMode::Off,
+            Sf2LoopMode::Continuous | Sf2LoopMode::UntilRelease => LoopMode::Forward,
+        });
+        zone.loop_while_held = region.loop_mode == Sf2LoopMode::UntilRelease;
+        Ok(zone)
+    }
+    pub fn contains(&self, note: u8, velocity: u8) -> bool {
+        (self.lokey..=self.hikey).contains(&note) && (self.lovel..=self.hivel).contains(&velocity)
+    }
+    // whether it's the zone's turn at the round robin step
+    fn is_turn(&self, step: u32) -> bool {
+        self.round_robin == 0 || step % self.round_robin as u32 == self.round_robin_position as u32
+    }
+}
+
+/// All the zones of an instrument. Zones may overlap, and then a note plays all of them layered
+#[derive(Default)]
+pub struct Keymap<'a> {
+    pub zones: Vec<Zone<'a>>,
+}
+
+impl<'a> Keymap<'a> {
+    /// A keymap with a single sample spread over the whole keyboard
+    pub fn single(data: SampleInterp<'a>) -> Keymap<'a> {
+        Keymap {
+            zones: vec![Zone::new(data)],
+        }
+    }
+    /// Loads the instrument the params point to. Allocates a lot, so don't call this from the audio thread
+    pub fn from_params(params: &SamplerParams) -> Keymap<'a> {
+        let sample_name = params.sample_name.read().unwrap().clone();
+        let keymap = if let Some(preset) = &sample_name.1 {
+            Keymap::from_sf2(&sample_name.path(), preset.index)
+        } else if sample_name.is_sfz() {
+            Keymap::from_sfz(&sample_name.path())
+        } else {
+            params.load_sample();
+            return Keymap::single(SampleInterp::from_params(params));
+        };
+        match keymap {
+            Ok(keymap) => keymap,
+            Err(err) => {
+                println!("Error in instrument loading: {sample_name}. {err}");
+                Keymap::default()
+            }
+        }
+    }
+    pub fn from_sfz(path: &Path) -> Result<Keymap<'a>, KeymapError> {
+        let regions = sfz::load_sfz(path).map_err(KeymapError::Sfz)?;
+        // regions often share a sample, like velocity layers with different volumes, so each file is only decoded once
+        let mut samples = HashMap::new();
+        let mut zones = Vec::with_capacity(regions.len());
+        for region in &regions {
+            let sample = samples
+                .entry(&region.sample)
+                .or_insert_with(|| load_audio(&region.sample).map_err(|err| err.to_string()));
+            let zone = match sample {
+                Ok(sample) => Zone::from_region(region, sample),
+                Err(err) => Err(KeymapError::Sample(region.sample.clone(), err.clone())),
+            };
+            // one broken region shouldn't take the rest of the instrument down with it
+            match zone {
+                Ok(zone) => zones.push(zone),
+                Err(err) => println!(
+                    "Error in instrument loading: {}, skipping a region. {err}",
+                    path.display()
+                ),
+            }
+        }
+        Ok(Keymap { zones })
+    }
+    pub fn from_sf2(path: &Path, preset: usize) -> Result<Keymap<'a>, KeymapError> {
+        let sound_font = SoundFont::open(path).map_err(KeymapError::Sf2)?;
+        let mut zones = Vec::new();
+        for region in sound_font.regions(preset).iter() {
+            match Zone::from_sf2_region(region, &sound_font.sample_data) {
+                Ok(zone) => zones.push(zone),
+                Err(err) => println!(
+                    "Error in instrument loading: {}, skipping a region. {err}",
+                    path.display()
+                ),
+            }
+        }
+        Ok(Keymap { zones })
+    }
+    /// The index of the next zone from `from` on that plays when the note is pressed with the velocity.
+    /// Of the zones in a round robin group, only the one whose turn it is at `step` plays.
+    /// Lets the audio thread go through the zones without allocating
+    pub fn next_zone(&self, from: usize, note: u8, velocity: u8, step: u32) -> Option<usize> {
+        (from..self.zones.len()).find(|&i| {
+            let zone = &self.zones[i];
+            !zone.on_release && zone.contains(note, velocity) && zone.is_turn(step)
+        })
+    }
+    /// Like next_zone, but for the zones that play when the note is let go
+    pub fn next_release_zone(&self, from: usize, note: u8, velocity: u8) -> Option<usize> {
+        (from..self.zones.len()).find(|&i| {
+            let zone = &self.zones[i];
+            zone.on_release && zone.contains(note, velocity)
+        })
+    }
+    /// How many presses it takes for the zones taking turns on the note and velocity to come around again.
+    /// Only the first zone that takes turns counts. 0 if there's none
+    pub fn round_robin_len(&self, note: u8, velocity: u8) -> usize {
+        self.zones
+            .iter()
+            .find(|zone| zone.round_robin != 0 && zone.contains(note, velocity))
+            .map_or(0, |zone| zone.round_robin as usize)
+    }
+}
+
+#[derive(Debug)]
+pub enum KeymapError {
+    Sfz(SfzError),
+    Sf2(Sf2Error),
+    // the path of the sample, and what went wrong
+    Sample(PathBuf, String),
+}
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeymapError::Sfz(err) => write!(f, "{err}"),
+            KeymapError::Sf2(err) => write!(f, "{err}"),
+            KeymapError::Sample(path, err) => write!(f, "couldn't load {}: {err}", path.display()),
+        }
+    }
+}
+
+/// Where each key is in its round robin. Lives outside the keymap, so it survives loading a new one
+pub struct RoundRobinState {
+    steps: [u32; 128],
+    // xorshift state for random round robins. Never 0
+    rng: u32,
+}
+
+impl RoundRobinState {
+    pub fn new() -> RoundRobinState {
+        RoundRobinState {
+            steps: [0; 128],
+            rng: 0x9E37_79B9,
+        }
+    }
+    /// The current step of the key
+    pub fn step(&self, note: u8) -> u32 {
+        self.steps[note as usize % 128]
+    }
+    /// Moves the key on to the next of its `len` alternatives and returns the new step
+    pub fn advance(&mut self, note: u8, len: usize, mode: RoundRobin) -> u32 {
+        let len = len as u32;
+        if len > 1 {
+            let skip = match mode {
+                RoundRobin::Sequential => 1,
+                // skipping ahead anywhere from 1 to len - 1 steps never lands on the same zone twice in a row
+                RoundRobin::Random => 1 + self.next_random() % (len - 1),
+            };
+            let step = &mut self.steps[note as usize % 128];
+            *step = (*step % len + skip) % len;
+        }
+        self.step(note)
+    }
+    fn next_random(&mut self) -> u32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng
+    }
+}
+
+#[test]
+fn offset_past_the_end() {
+    let sample = Sample {
+        data: vec![0.; 1024],
+        channels: 1,
+        sample_rate: 44100.,
+    };
+    let region = Region {
+        offset: Some(2048),
+        ..Region::default()
+    };
+    let zone = Zone::from_region(&region, &sample).unwrap();
+    let (start, end) = super::calc_play_range(&super::SmoothedValues::default(), &zone);
+    assert!(start < end && end <= 1.);
+}
+
+#[test]
+fn short_samples_are_padded() {
+    // a single cycle, much shorter than the mips need
+    let sample = Sample {
+        data: vec![0.5; 64],
+        channels: 2,
+        sample_rate: 44100.,
+    };
+    let region = Region {
+        loop_end: Some(32),
+        ..Region::default()
+    };
+    let zone = Zone::from_region(&region, &sample).unwrap();
+    assert_eq!(zone.data.len, padded_frames(32));
+    assert_eq!(zone.loop_end, Some(32. / padded_frames(32) as f32));
+}
+
+#[test]
+fn random_round_robin_never_repeats() {
+    let mut state = RoundRobinState::new();
+    let mut last = state.step(60);
+    for _ in 0..1000 {
+        let step = state.advance(60, 3, RoundRobin::Random);
+        assert!(step < 3);
+        assert_ne!(step, last);
+        last = step;
+    }
+}
@@ -0,0 +1,594 @@
+// Reader for SoundFont 2 files. Only the parts that map onto the sampler are read: the preset, instrument and sample headers,
+// the generators that set up key ranges, tuning, volume, pan and loops, and the 16 bit sample data.
+// Modulators and the 24 bit extension are skipped. The format is described in the SoundFont 2.04 spec
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+
+// generator operators
+const START_ADDRS_OFFSET: u16 = 0;
+const END_ADDRS_OFFSET: u16 = 1;
+const STARTLOOP_ADDRS_OFFSET: u16 = 2;
+const ENDLOOP_ADDRS_OFFSET: u16 = 3;
+const START_ADDRS_COARSE_OFFSET: u16 = 4;
+const END_ADDRS_COARSE_OFFSET: u16 = 12;
+const PAN: u16 = 17;
+const INSTRUMENT: u16 = 41;
+const KEY_RANGE: u16 = 43;
+const VEL_RANGE: u16 = 44;
+const STARTLOOP_ADDRS_COARSE_OFFSET: u16 = 45;
+const INITIAL_ATTENUATION: u16 = 48;
+const ENDLOOP_ADDRS_COARSE_OFFSET: u16 = 50;
+const COARSE_TUNE: u16 = 51;
+const FINE_TUNE: u16 = 52;
+const SAMPLE_ID: u16 = 53;
+const SAMPLE_MODES: u16 = 54;
+const EXCLUSIVE_CLASS: u16 = 57;
+const OVERRIDING_ROOT_KEY: u16 = 58;
+// one past the last generator defined by the spec
+const N_GENERATORS: usize = 61;
+
+#[derive(Debug)]
+pub enum Sf2Error {
+    Io(io::Error),
+    // the file isn't laid out like a sound font. Says what's wrong
+    Format(&'static str),
+}
+
+impl fmt::Display for Sf2Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sf2Error::Io(err) => write!(f, "{err}"),
+            Sf2Error::Format(err) => write!(f, "not a valid sound font: {err}"),
+        }
+    }
+}
+
+impl From<io::Error> for Sf2Error {
+    fn from(err: io::Error) -> Sf2Error {
+        Sf2Error::Io(err)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sf2LoopMode {
+    NoLoop,
+    Continuous,
+    // loops while the key is held, then plays on to the end
+    UntilRelease,
+}
+
+#[derive(Clone, Debug)]
+pub struct PresetHeader {
+    pub name: String,
+    // the preset's zones in pbag
+    bags: Range<usize>,
+}
+
+struct InstrumentHeader {
+    // the instrument's zones in ibag
+    bags: Range<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SampleHeader {
+    // positions are in sample frames from the start of the sample data
+    pub start: u32,
+    pub end: u32,
+    pub loop_start: u32,
+    pub loop_end: u32,
+    pub sample_rate: u32,
+    pub original_pitch: u8,
+    // in cents
+    pub pitch_correction: i8,
+}
+
+/// A zone of an instrument, with the generators of the preset playing it applied
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sf2Region {
+    pub sample: usize,
+    // positions are in sample frames from the start of the sample data
+    pub start: u32,
+    pub end: u32,
+    pub loop_start: u32,
+    pub loop_end: u32,
+    pub sample_rate: u32,
+    pub lokey: u8,
+    pub hikey: u8,
+    pub lovel: u8,
+    pub hivel: u8,
+    pub root: u8,
+    // in cents
+    pub tune: i32,
+    // in dB
+    pub attenuation: f32,
+    // -1 to 1
+    pub pan: f32,
+    pub loop_mode: Sf2LoopMode,
+    // a note in an exclusive class cuts off the other notes in it. 0 is no class
+    pub exclusive_class: u16,
+}
+
+// the generators of a zone, indexed by operator. Amounts are kept raw, since their type depends on the operator
+type Generators = [Option<[u8; 2]>; N_GENERATORS];
+
+fn amount_i16(generators: &Generators, operator: u16) -> Option<i16> {
+    generators[operator as usize].map(i16::from_le_bytes)
+}
+fn amount_u16(generators: &Generators, operator: u16) -> Option<u16> {
+    generators[operator as usize].map(u16::from_le_bytes)
+}
+// ranges are stored as a low and a high byte
+fn amount_range(generators: &Generators, operator: u16) -> (u8, u8) {
+    match generators[operator as usize] {
+        Some([lo, hi]) => (lo, hi),
+        None => (0, 127),
+    }
+}
+
+pub struct SoundFont {
+    pub presets: Vec<PresetHeader>,
+    pub samples: Vec<SampleHeader>,
+    // 16 bit mono sample data for all of the samples
+    pub sample_data: Vec<i16>,
+    instruments: Vec<InstrumentHeader>,
+    preset_bags: Vec<usize>,
+    preset_generators: Vec<(u16, [u8; 2])>,
+    instrument_bags: Vec<usize>,
+    instrument_generators: Vec<(u16, [u8; 2])>,
+}
+
+impl SoundFont {
+    /// Reads the whole sound font, sample data included
+    pub fn open(path: &Path) -> Result<SoundFont, Sf2Error> {
+        SoundFont::read(path, true)
+    }
+    /// The names of the presets, without loading any sample data
+    pub fn preset_names(path: &Path) -> Result<Vec<String>, Sf2Error> {
+        let sound_font = SoundFont::read(path, false)?;
+        Ok(sound_font
+            .presets
+            .into_iter()
+            .map(|preset| preset.name)
+            .collect())
+    }
+    fn read(path: &Path, load_samples: bool) -> Result<SoundFont, Sf2Error> {
+        let mut file = File::open(path)?;
+        let (id, _) = read_chunk_header(&mut file)?;
+        let mut form = [0; 4];
+        file.read_exact(&mut form)?;
+        if &id != b"RIFF" || &form != b"sfbk" {
+            return Err(Sf2Error::Format("missing the RIFF sfbk header"));
+        }
+        let mut pdta = None;
+        let mut sample_data = Vec::new();
+        // the top level is made of the INFO, sdta and pdta lists
+        while let Ok((id, size)) = read_chunk_header(&mut file) {
+            let mut list_type = [0; 4];
+            if &id != b"LIST" || size < 4 {
+                skip(&mut file, size.into())?;
+                continue;
+            }
+            file.read_exact(&mut list_type)?;
+            match &list_type {
+                b"pdta" => pdta = Some(read_bytes(&mut file, size - 4, (size - 4).into())?),
+                b"sdta" if load_samples => sample_data = read_sample_data(&mut file, size - 4)?,
+                _ => skip(&mut file, (size - 4).into())?,
+            }
+        }
+        let pdta = pdta.ok_or(Sf2Error::Format("missing the pdta list"))?;
+        let mut sound_font = SoundFont::from_pdta(&pdta)?;
+        sound_font.sample_data = sample_data;
+        Ok(sound_font)
+    }
+    fn from_pdta(pdta: &[u8]) -> Result<SoundFont, Sf2Error> {
+        let mut phdr = None;
+        let mut pbag = None;
+        let mut pgen = None;
+        let mut inst = None;
+        let mut ibag = None;
+        let mut igen = None;
+        let mut shdr = None;
+        let mut rest = pdta;
+        while rest.len() >= 8 {
+            let size = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+            let data = rest.get(8..8 + size).ok_or(Sf2Error::Format(
+                "a pdta chunk goes past the end of the list",
+            ))?;
+            match &rest[..4] {
+                b"phdr" => phdr = Some(data),
+                b"pbag" => pbag = Some(data),
+                b"pgen" => pgen = Some(data),
+                b"inst" => inst = Some(data),
+                b"ibag" => ibag = Some(data),
+                b"igen" => igen = Some(data),
+                b"shdr" => shdr = Some(data),
+                _ => (),
+            }
+            rest = rest.get(8 + size + size % 2..).unwrap_or(&[]);
+        }
+        let missing = Sf2Error::Format("missing a chunk in the pdta list");
+        let (phdr, pbag, pgen) = match (phdr, pbag, pgen) {
+            (Some(phdr), Some(pbag), Some(pgen)) => (phdr, pbag, pgen),
+            _ => return Err(missing),
+        };
+        let (inst, ibag, igen, shdr) = match (inst, ibag, igen, shdr) {
+            (Some(inst), Some(ibag), Some(igen), Some(shdr)) => (inst, ibag, igen, shdr),
+            _ => return Err(missing),
+        };
+        // every header list ends with a terminal record, which only marks where the last real record's zones end
+        let presets = phdr
+            .chunks_exact(38)
+            .zip(phdr.chunks_exact(38).skip(1))
+            .map(|(record, next)| PresetHeader {
+                name: read_name(&record[..20]),
+                bags: u16::from_le_bytes([record[24], record[25]]) as usize
+                    ..u16::from_le_bytes([next[24], next[25]]) as usize,
+            })
+            .collect::<Vec<PresetHeader>>();
+        let instruments = inst
+            .chunks_exact(22)
+            .zip(inst.chunks_exact(22).skip(1))
+            .map(|(record, next)| InstrumentHeader {
+                bags: u16::from_le_bytes([record[20], record[21]]) as usize
+                    ..u16::from_le_bytes([next[20], next[21]]) as usize,
+            })
+            .collect::<Vec<InstrumentHeader>>();
+        let mut samples = shdr
+            .chunks_exact(46)
+            .map(|record| {
+                let word = |i: usize| {
+                    u32::from_le_bytes([record[i], record[i + 1], record[i + 2], record[i + 3]])
+                };
+                SampleHeader {
+                    start: word(20),
+                    end: word(24),
+                    loop_start: word(28),
+                    loop_end: word(32),
+                    sample_rate: word(36),
+                    original_pitch: record[40],
+                    pitch_correction: record[41] as i8,
+                }
+            })
+            .collect::<Vec<SampleHeader>>();
+        // the terminal sample is just a name
+        samples.pop();
+        if presets.is_empty() || instruments.is_empty() || samples.is_empty() {
+            return Err(Sf2Error::Format("missing presets, instruments or samples"));
+        }
+        let bags = |data: &[u8]| {
+            data.chunks_exact(4)
+                .map(|record| u16::from_le_bytes([record[0], record[1]]) as usize)
+                .collect::<Vec<usize>>()
+        };
+        let generators = |data: &[u8]| {
+            data.chunks_exact(4)
+                .map(|record| {
+                    (
+                        u16::from_le_bytes([record[0], record[1]]),
+                        [record[2], record[3]],
+                    )
+                })
+                .collect::<Vec<(u16, [u8; 2])>>()
+        };
+        // the terminal records of the zone lists point one past the last generator, so they stay
+        Ok(SoundFont {
+            presets,
+            samples,
+            sample_data: Vec::new(),
+            instruments,
+            preset_bags: bags(pbag),
+            preset_generators: generators(pgen),
+            instrument_bags: bags(ibag),
+            instrument_generators: generators(igen),
+        })
+    }
+    /// The zones played by the preset, with all the generators of the preset and instrument zones resolved
+    pub fn regions(&self, preset: usize) -> Vec<Sf2Region> {
+        let mut regions = Vec::new();
+        let preset = match self.presets.get(preset) {
+            Some(preset) => preset,
+            None => return regions,
+        };
+        let preset_zones = zones(
+            &self.preset_bags,
+            &self.preset_generators,
+            preset.bags.clone(),
+            INSTRUMENT,
+        );
+        for preset_zone in preset_zones.iter() {
+            let instrument = match amount_u16(preset_zone, INSTRUMENT)
+                .and_then(|instrument| self.instruments.get(instrument as usize))
+            {
+                Some(instrument) => instrument,
+                None => continue,
+            };
+            let instrument_zones = zones(
+                &self.instrument_bags,
+                &self.instrument_generators,
+                instrument.bags.clone(),
+                SAMPLE_ID,
+            );
+            for instrument_zone in instrument_zones.iter() {
+                if let Some(region) = self.region(preset_zone, instrument_zone) {
+                    regions.push(region);
+                }
+            }
+        }
+        regions
+    }
+    fn region(&self, preset_zone: &Generators, zone: &Generators) -> Option<Sf2Region> {
+        let sample_id = amount_u16(zone, SAMPLE_ID)? as usize;
+        let sample = self.samples.get(sample_id)?;
+        // the key and velocity ranges are where both the preset's and the instrument's ranges overlap
+        let (preset_lokey, preset_hikey) = amount_range(preset_zone, KEY_RANGE);
+        let (lokey, hikey) = amount_range(zone, KEY_RANGE);
+        let (preset_lovel, preset_hivel) = amount_range(preset_zone, VEL_RANGE);
+        let (lovel, hivel) = amount_range(zone, VEL_RANGE);
+        let (lokey, hikey) = (lokey.max(preset_lokey), hikey.min(preset_hikey).min(127));
+        let (lovel, hivel) = (lovel.max(preset_lovel), hivel.min(preset_hivel).min(127));
+        if lokey > hikey || lovel > hivel {
+            return None;
+        }
+        // the address offsets only exist on instrument zones
+        let offset = |fine: u16, coarse: u16| {
+            amount_i16(zone, fine).unwrap_or(0) as i64
+                + amount_i16(zone, coarse).unwrap_or(0) as i64 * 32768
+        };
+        let position = |base: u32, offset: i64| (base as i64 + offset).max(0) as u32;
+        let start = position(
+            sample.start,
+            offset(START_ADDRS_OFFSET, START_ADDRS_COARSE_OFFSET),
+        );
+        let end = position(
+            sample.end,
+            offset(END_ADDRS_OFFSET, END_ADDRS_COARSE_OFFSET),
+        );
+        let end = end.min(self.sample_data.len() as u32);
+        if start >= end {
+            return None;
+        }
+        let loop_start = position(
+            sample.loop_start,
+            offset(STARTLOOP_ADDRS_OFFSET, STARTLOOP_ADDRS_COARSE_OFFSET),
+        );
+        let loop_end = position(
+            sample.loop_end,
+            offset(ENDLOOP_ADDRS_OFFSET, ENDLOOP_ADDRS_COARSE_OFFSET),
+        );
+        // everything else the preset sets is added on top of the instrument
+        let sum = |operator: u16| {
+            amount_i16(zone, operator).unwrap_or(0) as i32
+                + amount_i16(preset_zone, operator).unwrap_or(0) as i32
+        };
+        let root = match amount_i16(zone, OVERRIDING_ROOT_KEY) {
+            Some(root) if (0..128).contains(&root) => root as u8,
+            _ => sample.original_pitch.min(127),
+        };
+        let loop_mode = match amount_u16(zone, SAMPLE_MODES).unwrap_or(0) {
+            1 => Sf2LoopMode::Continuous,
+            3 => Sf2LoopMode::UntilRelease,
+            _ => Sf2LoopMode::NoLoop,
+        };
+        Some(Sf2Region {
+            sample: sample_id,
+            start,
+            end,
+            loop_start,
+            loop_end,
+            sample_rate: sample.sample_rate,
+            lokey,
+            hikey,
+            lovel,
+            hivel,
+            root,
+            tune: sum(COARSE_TUNE) * 100 + sum(FINE_TUNE) + sample.pitch_correction as i32,
+            // stored in centibels
+            attenuation: sum(INITIAL_ATTENUATION).max(0) as f32 / 10.,
+            // stored in 0.1% steps from -500 (left) to 500 (right)
+            pan: (sum(PAN) as f32 / 500.).clamp(-1., 1.),
+            loop_mode,
+            exclusive_class: amount_u16(zone, EXCLUSIVE_CLASS).unwrap_or(0),
+        })
+    }
+}
+
+// the zones in the range of bags, with the global zone's generators applied to each of them.
+// A zone is global if it's the first one and doesn't end with the `last` generator (instrument or sample id)
+fn zones(
+    bags: &[usize],
+    generators: &[(u16, [u8; 2])],
+    range: Range<usize>,
+    last: u16,
+) -> Vec<Generators> {
+    let mut global: Generators = [None; N_GENERATORS];
+    let mut zones = Vec::new();
+    let first = range.start;
+    for bag in range.start..range.end.min(bags.len().saturating_sub(1)) {
+        let zone_generators = match generators.get(bags[bag]..bags[bag + 1]) {
+            Some(zone_generators) => zone_generators,
+            None => continue,
+        };
+        let mut zone = global;
+        for &(operator, amount) in zone_generators {
+            if (operator as usize) < N_GENERATORS {
+                zone[operator as usize] = Some(amount);
+            }
+        }
+        match zone_generators.last() {
+            Some(&(operator, _)) if operator == last => zones.push(zone),
+            _ if bag == first => global = zone,
+            // zones without an instrument or sample outside of the global zone are to be ignored
+            _ => (),
+        }
+    }
+    zones
+}
+
+fn read_chunk_header(file: &mut File) -> io::Result<([u8; 4], u32)> {
+    let mut header = [0; 8];
+    file.read_exact(&mut header)?;
+    let id = [header[0], header[1], header[2], header[3]];
+    let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    Ok((id, size))
+}
+
+// reads a chunk of `size` bytes, which can't be more than the `limit` bytes left in its list
+fn read_bytes(file: &mut File, size: u32, limit: u64) -> Result<Vec<u8>, Sf2Error> {
+    // the size comes from the file, so it's checked before anything gets allocated for it
+    let left = file
+        .metadata()?
+        .len()
+        .saturating_sub(file.stream_position()?);
+    if u64::from(size) > limit.min(left) {
+        return Err(Sf2Error::Format("a chunk goes past the end of its list"));
+    }
+    let mut bytes = vec![0; size as usize];
+    file.read_exact(&mut bytes)?;
+    // chunks are padded to an even size
+    skip(file, (size % 2).into())?;
+    Ok(bytes)
+}
+
+fn skip(file: &mut File, size: u64) -> io::Result<()> {
+    file.seek(SeekFrom::Current(size as i64)).map(|_| ())
+}
+
+// reads the smpl chunk of the sdta list. The 24 bit extension in sm24 is skipped
+fn read_sample_data(file: &mut File, size: u32) -> Result<Vec<i16>, Sf2Error> {
+    let mut remaining = size as i64;
+    let mut sample_data = Vec::new();
+    while remaining >= 8 {
+        let (id, chunk_size) = read_chunk_header(file)?;
+        remaining -= 8;
+        // widened, so padding a chunk of u32::MAX bytes can't overflow
+        let padded_size = u64::from(chunk_size) + u64::from(chunk_size % 2);
+        if &id == b"smpl" {
+            sample_data = read_bytes(file, chunk_size, remaining as u64)?
+                .chunks_exact(2)
+                .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+                .collect();
+        } else {
+            skip(file, padded_size)?;
+        }
+        remaining -= padded_size as i64;
+    }
+    Ok(sample_data)
+}
+
+// names are 20 bytes, padded with zeroes
+fn read_name(bytes: &[u8]) -> String {
+    let end = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).trim().to_string()
+}
+
+#[test]
+fn resolve_regions() {
+    fn chunk(pdta: &mut Vec<u8>, id: &[u8; 4], records: &[Vec<u8>]) {
+        let data = records.concat();
+        pdta.extend_from_slice(id);
+        pdta.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        pdta.extend(data);
+    }
+    // a header record: the name, then the fields that matter here, then zeroes up to the record's length
+    fn record(len: usize, fields: &[u8]) -> Vec<u8> {
+        let mut record = vec![0; 20];
+        record.extend_from_slice(fields);
+        record.resize(len, 0);
+        record
+    }
+    fn bag(generator: u16) -> Vec<u8> {
+        [generator.to_le_bytes(), [0, 0]].concat()
+    }
+    fn amount(operator: u16, amount: i16) -> Vec<u8> {
+        [operator.to_le_bytes(), amount.to_le_bytes()].concat()
+    }
+    fn range(operator: u16, lo: u8, hi: u8) -> Vec<u8> {
+        [operator.to_le_bytes(), [lo, hi]].concat()
+    }
+    let mut sample = vec![];
+    for field in [0_u32, 50, 10, 30, 44100] {
+        sample.extend_from_slice(&field.to_le_bytes());
+    }
+    // original pitch, and a pitch correction of -2 cents
+    sample.extend_from_slice(&[60, (-2_i8) as u8]);
+
+    let mut pdta = vec![];
+    chunk(
+        &mut pdta,
+        b"phdr",
+        &[
+            record(38, &[0, 0, 0, 0, 0, 0]),
+            record(38, &[0, 0, 0, 0, 2, 0]),
+        ],
+    );
+    chunk(&mut pdta, b"pbag", &[bag(0), bag(1), bag(5)]);
+    chunk(
+        &mut pdta,
+        b"pgen",
+        &[
+            // the preset's global zone
+            amount(COARSE_TUNE, 1),
+            range(KEY_RANGE, 40, 80),
+            range(VEL_RANGE, 50, 127),
+            amount(FINE_TUNE, 3),
+            amount(INSTRUMENT, 0),
+        ],
+    );
+    chunk(
+        &mut pdta,
+        b"inst",
+        &[record(22, &[0, 0]), record(22, &[3, 0])],
+    );
+    chunk(&mut pdta, b"ibag", &[bag(0), bag(2), bag(8), bag(10)]);
+    chunk(
+        &mut pdta,
+        b"igen",
+        &[
+            // the instrument's global zone
+            range(VEL_RANGE, 0, 100),
+            amount(FINE_TUNE, 10),
+            range(KEY_RANGE, 60, 127),
+            amount(FINE_TUNE, 20),
+            amount(START_ADDRS_OFFSET, 4),
+            amount(END_ADDRS_COARSE_OFFSET, 1),
+            amount(STARTLOOP_ADDRS_OFFSET, -2),
+            amount(SAMPLE_ID, 0),
+            // outside of the preset's key range
+            range(KEY_RANGE, 0, 30),
+            amount(SAMPLE_ID, 0),
+        ],
+    );
+    chunk(&mut pdta, b"shdr", &[record(46, &sample), record(46, &[])]);
+
+    let mut sound_font = SoundFont::from_pdta(&pdta).unwrap();
+    sound_font.sample_data = vec![0; 100];
+    assert_eq!(
+        sound_font.regions(0),
+        vec![Sf2Region {
+            sample: 0,
+            start: 4,
+            // pushed 32768 frames on, then held to the end of the sample data
+            end: 100,
+            loop_start: 8,
+            loop_end: 30,
+            sample_rate: 44100,
+            lokey: 60,
+            hikey: 80,
+            lovel: 50,
+            hivel: 100,
+            root: 60,
+            // the preset's tuning is added to the instrument zone's, whose fine tune replaces the global one
+            tune: 100 + 3 + 20 - 2,
+            attenuation: 0.,
+            pan: 0.,
+            loop_mode: Sf2LoopMode::NoLoop,
+            exclusive_class: 0,
+        }]
+    );
+}
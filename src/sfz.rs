@@ -0,0 +1,382 @@
+// Parser for SFZ instruments. Only the headers and opcodes the sampler can do something with are read, the rest is skipped.
+// The format is described at https://sfzformat.com
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SfzLoopMode {
+    NoLoop,
+    // plays the whole sample, no matter when the key is let go
+    OneShot,
+    LoopContinuous,
+    // loops while the key is held, then plays on to the end
+    LoopSustain,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SfzTrigger {
+    Attack,
+    // plays when the key is let go, like the damper noise of a piano
+    Release,
+}
+
+/// A region with the opcodes of its group and the headers above it applied
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+    pub sample: PathBuf,
+    pub lokey: u8,
+    pub hikey: u8,
+    pub lovel: u8,
+    pub hivel: u8,
+    pub pitch_keycenter: u8,
+    // in cents
+    pub tune: i32,
+    // in dB
+    pub volume: f32,
+    // -100 to 100
+    pub pan: f32,
+    // None if the file doesn't say, and the params decide
+    pub loop_mode: Option<SfzLoopMode>,
+    // positions are in sample frames
+    pub loop_start: Option<u32>,
+    pub loop_end: Option<u32>,
+    pub offset: Option<u32>,
+    pub end: Option<u32>,
+    pub trigger: SfzTrigger,
+    pub group: u32,
+    pub off_by: u32,
+    // round robins: the region plays on every seq_length-th press of its key, starting with press seq_position
+    pub seq_length: u32,
+    pub seq_position: u32,
+}
+
+impl Default for Region {
+    fn default() -> Region {
+        Region {
+            sample: PathBuf::new(),
+            lokey: 0,
+            hikey: 127,
+            lovel: 0,
+            hivel: 127,
+            pitch_keycenter: 60,
+            tune: 0,
+            volume: 0.,
+            pan: 0.,
+            loop_mode: None,
+            loop_start: None,
+            loop_end: None,
+            offset: None,
+            end: None,
+            trigger: SfzTrigger::Attack,
+            group: 0,
+            off_by: 0,
+            seq_length: 1,
+            seq_position: 1,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SfzError {
+    Io(PathBuf, io::Error),
+    InvalidValue { opcode: String, value: String },
+    // opcodes before the first header have nothing to belong to
+    NoHeader(String),
+    // a region without a sample opcode
+    NoSample,
+}
+
+impl fmt::Display for SfzError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SfzError::Io(path, err) => write!(f, "couldn't read {}: {err}", path.display()),
+            SfzError::InvalidValue { opcode, value } => {
+                write!(f, "invalid value for {opcode}: {value}")
+            }
+            SfzError::NoHeader(opcode) => write!(f, "{opcode} isn't under any header"),
+            SfzError::NoSample => write!(f, "a region has no sample"),
+        }
+    }
+}
+
+// the header the following opcodes belong to. Global and master opcodes apply to every region below them,
+// like the group's do
+#[derive(Clone, Copy, PartialEq)]
+enum Header {
+    Control,
+    Global,
+    Master,
+    Group,
+    Region,
+    // headers the sampler doesn't know. Their opcodes are skipped
+    Other,
+}
+
+/// Reads the SFZ file and returns its regions. Sample paths are resolved relative to the file
+pub fn load_sfz(path: &Path) -> Result<Vec<Region>, SfzError> {
+    let text = fs::read_to_string(path).map_err(|err| SfzError::Io(path.to_path_buf(), err))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    parse_sfz(&text, dir)
+}
+
+pub fn parse_sfz(text: &str, dir: &Path) -> Result<Vec<Region>, SfzError> {
+    let text = strip_comments(text);
+    let mut regions = Vec::new();
+    let mut header = None;
+    let mut default_path = String::new();
+    // the opcodes of each level, in the order they appear
+    let mut global: Vec<(&str, &str)> = Vec::new();
+    let mut master: Vec<(&str, &str)> = Vec::new();
+    let mut group: Vec<(&str, &str)> = Vec::new();
+    let mut region: Vec<(&str, &str)> = Vec::new();
+    for token in tokenize(&text) {
+        match token {
+            Token::Header(name) => {
+                if header == Some(Header::Region) {
+                    regions.push(build_region(
+                        [&global, &master, &group, &region],
+                        dir,
+                        &default_path,
+                    )?);
+                }
+                region.clear();
+                let new_header = match name {
+                    "control" => Header::Control,
+                    "global" => Header::Global,
+                    "master" => Header::Master,
+                    "group" => Header::Group,
+                    "region" => Header::Region,
+                    _ => Header::Other,
+                };
+                // a new header clears the levels below it
+                match new_header {
+                    Header::Global => {
+                        global.clear();
+                        master.clear();
+                        group.clear();
+                    }
+                    Header::Master => {
+                        master.clear();
+                        group.clear();
+                    }
+                    Header::Group => group.clear(),
+                    _ => (),
+                }
+                header = Some(new_header);
+            }
+            Token::Opcode(opcode, value) => match header {
+                None => return Err(SfzError::NoHeader(opcode.to_string())),
+                Some(Header::Control) => {
+                    if opcode == "default_path" {
+                        default_path = value.to_string();
+                    }
+                }
+                Some(Header::Global) => global.push((opcode, value)),
+                Some(Header::Master) => master.push((opcode, value)),
+                Some(Header::Group) => group.push((opcode, value)),
+                Some(Header::Region) => region.push((opcode, value)),
+                Some(Header::Other) => (),
+            },
+        }
+    }
+    if header == Some(Header::Region) {
+        regions.push(build_region(
+            [&global, &master, &group, &region],
+            dir,
+            &default_path,
+        )?);
+    }
+    Ok(regions)
+}
+
+fn build_region(
+    levels: [&Vec<(&str, &str)>; 4],
+    dir: &Path,
+    default_path: &str,
+) -> Result<Region, SfzError> {
+    let mut region = Region::default();
+    let mut sample = None;
+    // later levels override earlier ones
+    for (opcode, value) in levels.iter().flat_map(|level| level.iter()) {
+        let invalid = || SfzError::InvalidValue {
+            opcode: opcode.to_string(),
+            value: value.to_string(),
+        };
+        match *opcode {
+            "sample" => sample = Some(*value),
+            "lokey" => region.lokey = parse_note(value).ok_or_else(invalid)?,
+            "hikey" => region.hikey = parse_note(value).ok_or_else(invalid)?,
+            // sets the key range and root at once
+            "key" => {
+                let key = parse_note(value).ok_or_else(invalid)?;
+                region.lokey = key;
+                region.hikey = key;
+                region.pitch_keycenter = key;
+            }
+            "lovel" => region.lovel = value.parse().map_err(|_| invalid())?,
+            "hivel" => region.hivel = value.parse().map_err(|_| invalid())?,
+            "pitch_keycenter" => region.pitch_keycenter = parse_note(value).ok_or_else(invalid)?,
+            "tune" => region.tune = value.parse().map_err(|_| invalid())?,
+            "volume" => region.volume = value.parse().map_err(|_| invalid())?,
+            "pan" => region.pan = value.parse().map_err(|_| invalid())?,
+            "loop_mode" | "loopmode" => {
+                region.loop_mode = Some(match *value {
+                    "no_loop" => SfzLoopMode::NoLoop,
+                    "one_shot" => SfzLoopMode::OneShot,
+                    "loop_continuous" => SfzLoopMode::LoopContinuous,
+                    "loop_sustain" => SfzLoopMode::LoopSustain,
+                    _ => return Err(invalid()),
+                })
+            }
+            "loop_start" | "loopstart" => {
+                region.loop_start = Some(value.parse().map_err(|_| invalid())?)
+            }
+            "loop_end" | "loopend" => region.loop_end = Some(value.parse().map_err(|_| invalid())?),
+            "offset" => region.offset = Some(value.parse().map_err(|_| invalid())?),
+            "end" => region.end = Some(value.parse().map_err(|_| invalid())?),
+            "trigger" => {
+                region.trigger = match *value {
+                    "release" => SfzTrigger::Release,
+                    // first and legato need to know about the other keys. They're played like attack for now
+                    "attack" | "first" | "legato" => SfzTrigger::Attack,
+                    _ => return Err(invalid()),
+                }
+            }
+            "group" => region.group = value.parse().map_err(|_| invalid())?,
+            "off_by" => region.off_by = value.parse().map_err(|_| invalid())?,
+            "seq_length" => region.seq_length = value.parse().map_err(|_| invalid())?,
+            "seq_position" => region.seq_position = value.parse().map_err(|_| invalid())?,
+            _ => (),
+        }
+    }
+    // sfz files are often made on windows
+    let sample = format!("{default_path}{}", sample.ok_or(SfzError::NoSample)?).replace('\\', "/");
+    region.sample = dir.join(sample);
+    Ok(region)
+}
+
+// notes can be numbers or names like c#4, where c4 is middle C (60)
+fn parse_note(value: &str) -> Option<u8> {
+    if let Ok(note) = value.parse::<u8>() {
+        return if note < 128 { Some(note) } else { None };
+    }
+    let mut chars = value.chars();
+    let base = match chars.next()?.to_ascii_lowercase() {
+        'c' => 0,
+        'd' => 2,
+        'e' => 4,
+        'f' => 5,
+        'g' => 7,
+        'a' => 9,
+        'b' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (accidental, octave) = if let Some(octave) = rest.strip_prefix('#') {
+        (1, octave)
+    } else if let Some(octave) = rest.strip_prefix('b') {
+        (-1, octave)
+    } else {
+        (0, rest)
+    };
+    let note = (octave.parse::<i32>().ok()? + 1) * 12 + base + accidental;
+    if (0..128).contains(&note) {
+        Some(note as u8)
+    } else {
+        None
+    }
+}
+
+fn strip_comments(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut rest = text;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("//") {
+            rest = match after.find('\n') {
+                Some(i) => &after[i..],
+                None => "",
+            };
+        } else if let Some(after) = rest.strip_prefix("/*") {
+            rest = match after.find("*/") {
+                Some(i) => &after[i + 2..],
+                None => "",
+            };
+            // keeps what was on either side of the comment apart
+            stripped.push(' ');
+        } else {
+            let c = rest.chars().next().unwrap();
+            stripped.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    stripped
+}
+
+enum Token<'a> {
+    Header(&'a str),
+    Opcode(&'a str, &'a str),
+}
+
+// values can have spaces in them (mostly sample paths), so a value goes on until the next opcode, header or line break
+fn tokenize(text: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    for line in text.lines() {
+        let mut rest = line;
+        while let Some(start) = rest.find(|c: char| !c.is_whitespace()) {
+            rest = &rest[start..];
+            if let Some(after) = rest.strip_prefix('<') {
+                let end = after.find('>').unwrap_or(after.len());
+                tokens.push(Token::Header(after[..end].trim()));
+                rest = after.get(end + 1..).unwrap_or("");
+                continue;
+            }
+            let eq = match rest.find('=') {
+                Some(eq) => eq,
+                // stray text that isn't an opcode
+                None => break,
+            };
+            let opcode = rest[..eq].trim();
+            let after = &rest[eq + 1..];
+            // the value ends where the next opcode's name or the next header starts
+            let value_end = match (after.find('='), after.find('<')) {
+                (_, Some(header)) if after[..header].find('=').is_none() => header,
+                (Some(next_eq), _) => after[..next_eq]
+                    .trim_end()
+                    .rfind(char::is_whitespace)
+                    .unwrap_or(0),
+                (None, _) => after.len(),
+            };
+            tokens.push(Token::Opcode(opcode, after[..value_end].trim()));
+            rest = &after[value_end..];
+        }
+    }
+    tokens
+}
+
+#[test]
+fn parse_regions() {
+    let text = r"
+        // a comment
+        <control> default_path=samples\
+        <group> lovel=64 loop_mode=one_shot group=1 off_by=2
+        <region> sample=kick hard.wav key=c4 /* inline */ volume=-6
+        <region> sample=snare.wav lokey=d4 hikey=e4 pitch_keycenter=d#4 tune=-20 seq_length=2 seq_position=2
+        <group> trigger=release
+        <region> sample=release.wav
+    ";
+    let regions = parse_sfz(text, Path::new("kit")).unwrap();
+    assert_eq!(regions.len(), 3);
+    assert_eq!(regions[0].sample, Path::new("kit/samples/kick hard.wav"));
+    assert_eq!((regions[0].lokey, regions[0].hikey), (60, 60));
+    assert_eq!(regions[0].volume, -6.);
+    assert_eq!(regions[0].loop_mode, Some(SfzLoopMode::OneShot));
+    assert_eq!((regions[1].lokey, regions[1].hikey), (62, 64));
+    assert_eq!(regions[1].pitch_keycenter, 63);
+    assert_eq!((regions[1].lovel, regions[1].group), (64, 1));
+    assert_eq!((regions[1].seq_length, regions[1].seq_position), (2, 2));
+    // the new group doesn't inherit from the old one
+    assert_eq!(regions[2].lovel, 0);
+    assert_eq!(regions[2].trigger, SfzTrigger::Release);
+}
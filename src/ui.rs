@@ -147,6 +147,8 @@ pub enum UiEvent {
     BeginSet(ParamPtr),
     EndSet(ParamPtr),
     ToggleParam(ParamPtr),
+    // steps to the next of the param's values, wrapping around at the end. The usize is the number of values
+    CycleParam(ParamPtr, usize),
     ChangeSample(SampleName),
     // TODO: SavePreset should take a name sometime in the future
 }
@@ -184,6 +186,17 @@ impl Model for UiData {
                     };
                     unsafe { self.gui_context.raw_end_set_parameter(*param_ptr) };
                 }
+                UiEvent::CycleParam(param_ptr, n_values) => {
+                    let last_value = n_values.saturating_sub(1).max(1) as f32;
+                    let norm_val = unsafe { param_ptr.normalized_value() };
+                    let next_value = ((norm_val * last_value).round() + 1.) % (last_value + 1.);
+                    unsafe { self.gui_context.raw_begin_set_parameter(*param_ptr) };
+                    unsafe {
+                        self.gui_context
+                            .raw_set_parameter_normalized(*param_ptr, next_value / last_value)
+                    };
+                    unsafe { self.gui_context.raw_end_set_parameter(*param_ptr) };
+                }
 
                 UiEvent::ChangeSample(table) => {
                     // println!("loading sample {}", table);
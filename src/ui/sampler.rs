@@ -1,9 +1,9 @@
 use std::sync::Arc;
 
-use crate::parameters::SamplerParams;
+use crate::parameters::{LoopMode, SamplerParams, TriggerMode};
 
 use super::{draw_on_off_button, make_knob, plot, UiData, UiEvent, ICON_DOWN_OPEN};
-use nih_plug::prelude::Param;
+use nih_plug::prelude::{Enum, Param};
 use vizia::*;
 // use vst::plugin::PluginParameters;
 pub fn draw_sampler_gui(cx: &mut Context) -> Handle<VStack> {
@@ -57,8 +57,9 @@ pub fn draw_sampler_gui(cx: &mut Context) -> Handle<VStack> {
             .top(Pixels(0.));
         HStack::new(cx, move |cx| {
             make_knob(cx, "Volume", false, params.volume.as_ptr(), |p| &p.volume);
-            // make_knob(cx, "Pan", true, params.pan.as_ptr(), |p| &p.pan);
+            make_knob(cx, "Pan", true, params.pan.as_ptr(), |p| &p.pan);
             make_knob(cx, "Pos", false, params.pos.as_ptr(), |p| &p.pos);
+            make_knob(cx, "End", false, params.end.as_ptr(), |p| &p.end);
             VStack::new(cx, move |cx| {
                 // TODO: Center these
                 HStack::new(cx, move |cx| {
@@ -68,11 +69,48 @@ pub fn draw_sampler_gui(cx: &mut Context) -> Handle<VStack> {
                 .child_bottom(Stretch(1.))
                 .child_top(Stretch(1.));
                 HStack::new(cx, move |cx| {
-                    draw_on_off_button(cx, params.is_looping.as_ptr()).class("small_on_off");
+                    let loop_mode = params.loop_mode.as_ptr();
+                    Button::new(
+                        cx,
+                        move |cx| {
+                            cx.emit(UiEvent::CycleParam(loop_mode, LoopMode::variants().len()));
+                        },
+                        |cx| Label::new(cx, UiData::params.map(|p| p.loop_mode.to_string())),
+                    )
+                    .class("mode_button");
                     Label::new(cx, "Loop");
                 })
                 .child_bottom(Stretch(1.))
                 .child_top(Stretch(1.));
+                HStack::new(cx, move |cx| {
+                    draw_on_off_button(cx, params.reverse.as_ptr()).class("small_on_off");
+                    Label::new(cx, "Reverse");
+                })
+                .child_bottom(Stretch(1.))
+                .child_top(Stretch(1.));
+                HStack::new(cx, move |cx| {
+                    let trigger_mode = params.trigger_mode.as_ptr();
+                    Button::new(
+                        cx,
+                        move |cx| {
+                            cx.emit(UiEvent::CycleParam(
+                                trigger_mode,
+                                TriggerMode::variants().len(),
+                            ));
+                        },
+                        |cx| Label::new(cx, UiData::params.map(|p| p.trigger_mode.to_string())),
+                    )
+                    .class("mode_button");
+                    Label::new(cx, "Trigger");
+                })
+                .child_bottom(Stretch(1.))
+                .child_top(Stretch(1.));
+                HStack::new(cx, move |cx| {
+                    draw_on_off_button(cx, params.retrigger.as_ptr()).class("small_on_off");
+                    Label::new(cx, "Retrigger");
+                })
+                .child_bottom(Stretch(1.))
+                .child_top(Stretch(1.));
             });
         })
         .top(Pixels(0.))
@@ -1,24 +1,78 @@
+use crate::envelope::Envelope;
+use crate::filter::VoiceFilter;
+
+// how long it takes to fade out a voice that has to be cut off, in seconds
+const DECLICK_TIME: f32 = 0.005;
 #[derive(Clone)]
 pub struct Voice {
+    // whether the note is still held. The voice keeps sounding until vol_env is done with the release
     pub is_on: bool,
+    // the key is up, but a pedal is keeping the note from being released
+    pub sustained: bool,
+    // the key was held when the sostenuto pedal went down
+    pub sostenuto: bool,
     pub sampler_phase: f32,
     pub current_note: u8,
+    // velocity of the note that triggered the voice, and the gain it maps to with the current velocity params
+    pub velocity: f32,
+    pub velocity_gain: f32,
+    // when the voice was last triggered, counted in notes. Used for finding the oldest voice
+    pub started: u64,
+    // the key press that started the voice. All the layers of one press share it, so letting go releases them together
+    pub press: u64,
     // these 2 are in midi notes, not in Hertz
     pub current_notepitch: f32,
     pub target_notepitch: f32,
     // how much current_notepitch should be increased with, if relevant
     pub increment: f32,
-    // pub filter: ModellingFilter,
+    // 1 when playing forwards, -1 when playing backwards
+    pub direction: f32,
+    // which of the sampler's data slots the voice is playing from
+    pub slot: usize,
+    // which zone of the slot's keymap the voice is playing
+    pub zone: usize,
+    // starting a voice in this choke group cuts the voice off. 0 is no group
+    pub off_by: u32,
+    // how far to move through the current mip per sample, and which mip to read from
+    pub ratio: f32,
+    pub current_mip: usize,
+    // for one-shotting the sample
+    pub is_done: bool,
+    // the phase has left the play range. Unlike running out of sample, the voice is faded out, since the range can move under it
+    pub out_of_range: bool,
+    // gain of the fade used to cut the voice off without clicking
+    declick_gain: f32,
+    declick_step: f32,
+    pub vol_env: Envelope,
+    pub filter: VoiceFilter,
 }
 impl Voice {
     pub fn new() -> Voice {
         Voice {
             is_on: false,
+            sustained: false,
+            sostenuto: false,
             current_note: 0,
+            velocity: 1.,
+            velocity_gain: 1.,
+            started: 0,
+            press: 0,
             current_notepitch: 0.,
             target_notepitch: 0.,
             increment: 0.,
             sampler_phase: 0.,
+            direction: 1.,
+            slot: 0,
+            zone: 0,
+            off_by: 0,
+            ratio: 0.,
+            current_mip: 0,
+            is_done: true,
+            out_of_range: false,
+            declick_gain: 1.,
+            declick_step: 0.,
+            vol_env: Envelope::new(),
+            filter: VoiceFilter::new(),
         }
     }
     pub fn update_pitch(&mut self) -> bool {
@@ -38,7 +92,54 @@ impl Voice {
         }
     }
 
+    /// Whether letting go of the note releases the voice, when `press` is the press being let go of.
+    /// A sustained voice belongs to a press that has been let go already
+    pub fn is_held_by(&self, note: u8, press: u64) -> bool {
+        self.current_note == note && self.press == press && self.is_on && !self.sustained
+    }
     pub fn release(&mut self) {
         self.is_on = false;
+        self.sustained = false;
+        self.vol_env.release();
+    }
+    /// Silences the voice right away, freeing it up for new notes
+    pub fn stop(&mut self) {
+        self.is_on = false;
+        self.sustained = false;
+        self.vol_env.reset();
+        self.reset_declick();
+    }
+    /// Whether the voice is making sound, including the release stage
+    pub fn is_active(&self) -> bool {
+        !self.vol_env.is_off()
+    }
+    /// Rough estimate of how loud the voice currently is
+    pub fn loudness(&self) -> f32 {
+        self.vol_env.output * self.velocity_gain * self.declick_gain
+    }
+    /// Starts fading the voice out quickly. The voice turns off when the fade is done
+    pub fn declick(&mut self, sample_rate: f32) {
+        if self.declick_step == 0. {
+            // times 2 because of internal oversampling
+            self.declick_step = 1. / (DECLICK_TIME * sample_rate * 2.);
+        }
+    }
+    pub fn is_declicking(&self) -> bool {
+        self.declick_step > 0.
+    }
+    pub fn reset_declick(&mut self) {
+        self.declick_gain = 1.;
+        self.declick_step = 0.;
+    }
+    /// Returns the gain of the declick fade and moves it one step forward
+    pub fn next_declick(&mut self) -> f32 {
+        if self.declick_step > 0. {
+            self.declick_gain -= self.declick_step;
+            if self.declick_gain <= 0. {
+                self.stop();
+                return 0.;
+            }
+        }
+        self.declick_gain
     }
 }